@@ -308,7 +308,7 @@ impl AliasContainer {
         match self.expand_alias(&command, chat_id, user_id, false) {
             Ok(_) => {
                 let data = self.entry(chat_id).or_insert_with(AliasData::new);
-                let user_defs = data.user_defs.entry(user_id).or_insert_with(HashMap::new);
+                let user_defs = data.user_defs.entry(user_id).or_default();
                 let alias = alias.to_lowercase();
                 let send = format!(
                     "**info** *alias* `${}` *set for user* **{}**",
@@ -4,6 +4,7 @@ use map::RollMap;
 pub(crate) mod utils;
 use utils::check_critics;
 use utils::parse_args;
+use utils::parse_probability_args;
 use utils::react_to_critic;
 use utils::search_critics;
 use utils::solve;
@@ -20,7 +21,7 @@ use tracing_unwrap::OptionExt;
 
 #[group]
 #[description = "Roll expression solving group"]
-#[commands(roll, reroll)]
+#[commands(roll, reroll, probability)]
 struct Roll;
 
 #[command]
@@ -33,6 +34,15 @@ async fn roll(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
     Ok(())
 }
 
+#[command]
+#[aliases("p", "prob")]
+#[min_args(1)]
+async fn probability(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let to_send = parse_probability_args(ctx, msg, args).await;
+    send_reply(ctx, msg, &to_send).await?;
+    Ok(())
+}
+
 #[command]
 #[aliases("rr")]
 async fn reroll(ctx: &Context, msg: &Message) -> CommandResult {
@@ -4,6 +4,7 @@ use havok_lib::dice::Critic;
 use havok_lib::error::Error;
 use havok_lib::roll;
 use havok_lib::roll::history::History;
+use havok_lib::solver::RandomSource;
 use havok_lib::solver::Solver;
 use serenity::framework::standard::Args;
 use serenity::framework::standard::CommandResult;
@@ -18,11 +19,21 @@ use tracing_unwrap::ResultExt;
 const TWEMOJI_NOT: &str = "🤨";
 const TWEMOJI_MIN: &str = "🥶";
 const TWEMOJI_MAX: &str = "🤩";
+const TWEMOJI_FUMBLE: &str = "💀";
+const TWEMOJI_GLITCH: &str = "⚠️";
+const TWEMOJI_SETS: &str = "🎲";
 
-fn format_havok_error(error: Error) -> String {
-    match error {
-        Error::Pest(_) => format!("**error**\n```{}\n```", error),
-        Error::Other(error) => format!("**error** *{}*", error),
+const SIMULATION_SAMPLES: u64 = 5_000;
+
+fn format_havok_error(error: Error, input: &str) -> String {
+    match error.span() {
+        Some(span) => format!(
+            "**error** `{}` *{}*\n```{}\n```",
+            error.code(),
+            error,
+            span.underline(input)
+        ),
+        None => format!("**error** `{}` *{}*", error.code(), error),
     }
 }
 
@@ -37,6 +48,7 @@ pub(crate) async fn react_to_critic(
                 Critic::Not => ReactionType::Unicode(TWEMOJI_NOT.to_string()),
                 Critic::Min => ReactionType::Unicode(TWEMOJI_MIN.to_string()),
                 Critic::Max => ReactionType::Unicode(TWEMOJI_MAX.to_string()),
+                Critic::Fumble => ReactionType::Unicode(TWEMOJI_FUMBLE.to_string()),
             };
             msg.react(ctx, twemoji).await?;
         }
@@ -63,9 +75,20 @@ pub(crate) async fn parse_args(
     match solve_expr(ctx, msg, &input).await {
         Ok(result) => {
             let critics = search_critics(&result);
+            let glitch = match result.get_glitch() {
+                Some(glitch) => Cow::Owned(format!("\n{} **{}**", TWEMOJI_GLITCH, glitch)),
+                None => Cow::Borrowed(""),
+            };
+            let sets = result.get_sets();
+            let sets = if sets.is_empty() {
+                Cow::Borrowed("")
+            } else {
+                let sets = sets.iter().map(ToString::to_string).collect::<Vec<_>>();
+                Cow::Owned(format!("\n{} **{}**", TWEMOJI_SETS, sets.join(", ")))
+            };
             let result = result.to_string();
             (
-                format!("**rolling** {}\n{}", alias, result),
+                format!("**rolling** {}\n{}{}{}", alias, result, glitch, sets),
                 check_critics(critics),
             )
         }
@@ -73,6 +96,35 @@ pub(crate) async fn parse_args(
     }
 }
 
+pub(crate) async fn parse_probability_args(ctx: &Context, msg: &Message, args: Args) -> String {
+    let input = parse_alias(ctx, msg, args).await;
+    let (input, has_alias) = match input {
+        Ok(input) => input,
+        Err(error) => return error,
+    };
+    let alias = if has_alias {
+        Cow::Owned(format!("*alias* `{}`", input))
+    } else {
+        Cow::Borrowed("")
+    };
+    // computing or simulating may take a while, keep it off the async workers
+    let probability = tokio::task::spawn_blocking({
+        let input = input.clone();
+        move || {
+            Solver::new(&input)?.probability(
+                SIMULATION_SAMPLES,
+                &mut RandomSource::new(&mut rand::thread_rng()),
+            )
+        }
+    })
+    .await
+    .unwrap_or_log();
+    match probability {
+        Ok(result) => format!("**probability** {}\n{}", alias, result),
+        Err(error) => format_havok_error(error, &input),
+    }
+}
+
 async fn solve_expr(ctx: &Context, msg: &Message, input: &str) -> Result<roll::Result, String> {
     solve(ctx, msg, Solver::new(input).unwrap_or_log()).await
 }
@@ -92,11 +144,11 @@ pub(crate) async fn solve(
             }
             Ok(result)
         }
-        Err(error) => Err(format_havok_error(error)),
+        Err(error) => Err(format_havok_error(error, solver.as_str())),
     }
 }
 
-pub(crate) fn search_critics(result: &roll::Result) -> Result<HashSet<Critic>, serenity::Error> {
+pub(crate) fn search_critics(result: &roll::Result) -> Result<HashSet<Critic>, &'static str> {
     let mut critics = HashSet::new();
     match result.get_result() {
         roll::Kind::Single(result) => {
@@ -118,13 +170,13 @@ pub(crate) fn search_critics(result: &roll::Result) -> Result<HashSet<Critic>, s
 fn search_critic(
     result: &roll::kind::Single,
     critics: &mut HashSet<Critic>,
-) -> Result<(), serenity::Error> {
+) -> Result<(), &'static str> {
     let mut has_roll = false;
     for kind in result.get_history().iter() {
         match kind {
             History::Roll(results) => {
                 has_roll = true;
-                for result in results.iter() {
+                for result in results.iter().filter(|r| r.is_counted()) {
                     match result.critic {
                         Critic::Not => {}
                         _ => {
@@ -133,19 +185,19 @@ fn search_critic(
                     }
                 }
             }
-            History::Fudge(_) => has_roll = true,
+            History::Fudge(_) | History::Percent(_) => has_roll = true,
             _ => (),
         }
     }
     if has_roll {
         Ok(())
     } else {
-        Err(serenity::Error::Other("no roll found"))
+        Err("no roll found")
     }
 }
 
 pub(crate) fn check_critics(
-    critics: Result<HashSet<Critic>, serenity::Error>,
+    critics: Result<HashSet<Critic>, &'static str>,
 ) -> Option<HashSet<Critic>> {
     match critics {
         Ok(critics) => {
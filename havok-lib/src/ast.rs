@@ -0,0 +1,688 @@
+use itertools::Itertools;
+use std::ops::RangeInclusive;
+
+/// Byte range of a sub-expression in the query
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    /// Smallest span covering both
+    pub fn join(&self, other: &Span) -> Self {
+        Self {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        }
+    }
+
+    /// Check if the span is unknown
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// Return the query with a line of carets under the span
+    pub fn underline(&self, input: &str) -> String {
+        let start = input.get(..self.start).map_or(0, |s| s.chars().count());
+        let width = input
+            .get(self.start..self.end)
+            .map_or(1, |s| s.chars().count())
+            .max(1);
+        format!("{}\n{}{}", input, " ".repeat(start), "^".repeat(width))
+    }
+}
+
+impl From<pest::Span<'_>> for Span {
+    fn from(value: pest::Span<'_>) -> Self {
+        Self::new(value.start(), value.end())
+    }
+}
+
+/// Binary operator between two expressions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Pow,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+impl Operator {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Operator::Add => "+",
+            Operator::Sub => "-",
+            Operator::Mul => "*",
+            Operator::Div => "/",
+            Operator::Rem => "%",
+            Operator::Pow => "**",
+            Operator::Lt => "<",
+            Operator::Le => "<=",
+            Operator::Gt => ">",
+            Operator::Ge => ">=",
+            Operator::Eq => "==",
+            Operator::Ne => "!=",
+        }
+    }
+
+    /// Binding power in the grammar, higher binds tighter, `-` prefix sits between `*` and `**`
+    pub fn precedence(&self) -> u8 {
+        match self {
+            Operator::Lt
+            | Operator::Le
+            | Operator::Gt
+            | Operator::Ge
+            | Operator::Eq
+            | Operator::Ne => 1,
+            Operator::Add | Operator::Sub => 2,
+            Operator::Mul | Operator::Div | Operator::Rem => 3,
+            Operator::Pow => 5,
+        }
+    }
+}
+
+impl Operator {
+    /// Apply on integers, `None` if the result isn't an integer or doesn't fit
+    pub(crate) fn int(&self, lhs: i64, rhs: i64) -> Option<i64> {
+        match self {
+            Operator::Add => lhs.checked_add(rhs),
+            Operator::Sub => lhs.checked_sub(rhs),
+            Operator::Mul => lhs.checked_mul(rhs),
+            Operator::Div => match lhs.checked_rem(rhs)? {
+                0 => lhs.checked_div(rhs),
+                _ => None,
+            },
+            Operator::Rem => lhs.checked_rem(rhs),
+            Operator::Pow => lhs.checked_pow(u32::try_from(rhs).ok()?),
+            Operator::Lt => Some((lhs < rhs).into()),
+            Operator::Le => Some((lhs <= rhs).into()),
+            Operator::Gt => Some((lhs > rhs).into()),
+            Operator::Ge => Some((lhs >= rhs).into()),
+            Operator::Eq => Some((lhs == rhs).into()),
+            Operator::Ne => Some((lhs != rhs).into()),
+        }
+    }
+
+    pub(crate) fn float(&self, lhs: f64, rhs: f64) -> f64 {
+        match self {
+            Operator::Add => lhs + rhs,
+            Operator::Sub => lhs - rhs,
+            Operator::Mul => lhs * rhs,
+            Operator::Div => lhs / rhs,
+            Operator::Rem => lhs % rhs,
+            Operator::Pow => lhs.powf(rhs),
+            Operator::Lt => f64::from(u8::from(lhs < rhs)),
+            Operator::Le => f64::from(u8::from(lhs <= rhs)),
+            Operator::Gt => f64::from(u8::from(lhs > rhs)),
+            Operator::Ge => f64::from(u8::from(lhs >= rhs)),
+            Operator::Eq => f64::from(u8::from(lhs == rhs)),
+            Operator::Ne => f64::from(u8::from(lhs != rhs)),
+        }
+    }
+}
+
+/// Built-in function, like `max(1d20, 1d20)`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Function {
+    Min,
+    Max,
+    Abs,
+    Floor,
+    Ceil,
+    Round,
+    Clamp,
+    /// Call of Cthulhu success level of a roll against a skill
+    Coc,
+}
+
+/// Call of Cthulhu success level of `roll` against `skill`, from -1 for a fumble to 4 for a critical
+fn coc_level(roll: i64, skill: i64) -> i64 {
+    if roll == 1 {
+        4
+    } else if roll >= 100 || (skill < 50 && roll >= 96) {
+        -1
+    } else if roll <= skill / 5 {
+        3
+    } else if roll <= skill / 2 {
+        2
+    } else if roll <= skill {
+        1
+    } else {
+        0
+    }
+}
+
+/// Name of a Call of Cthulhu success level
+pub fn coc_name(level: i64) -> &'static str {
+    match level {
+        4 => "critical",
+        3 => "extreme",
+        2 => "hard",
+        1 => "regular",
+        0 => "failure",
+        _ => "fumble",
+    }
+}
+
+impl Function {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Function::Min => "min",
+            Function::Max => "max",
+            Function::Abs => "abs",
+            Function::Floor => "floor",
+            Function::Ceil => "ceil",
+            Function::Round => "round",
+            Function::Clamp => "clamp",
+            Function::Coc => "coc",
+        }
+    }
+
+    /// Check if the function can be called with `count` arguments
+    pub fn accepts(&self, count: usize) -> bool {
+        match self {
+            Function::Min | Function::Max => count >= 1,
+            Function::Clamp => count == 3,
+            Function::Coc => count == 2,
+            Function::Abs | Function::Floor | Function::Ceil | Function::Round => count == 1,
+        }
+    }
+
+    /// Human readable amount of arguments
+    pub fn arity(&self) -> &'static str {
+        match self {
+            Function::Min | Function::Max => "at least 1 argument",
+            Function::Clamp => "3 arguments",
+            Function::Coc => "2 arguments",
+            Function::Abs | Function::Floor | Function::Ceil | Function::Round => "1 argument",
+        }
+    }
+
+    /// Apply on integers, `None` if the result doesn't fit or the arguments don't match
+    pub(crate) fn int(&self, args: &[i64]) -> Option<i64> {
+        match (self, args) {
+            (Function::Min, _) => args.iter().min().copied(),
+            (Function::Max, _) => args.iter().max().copied(),
+            (Function::Abs, [x]) => x.checked_abs(),
+            (Function::Floor | Function::Ceil | Function::Round, [x]) => Some(*x),
+            // not `i64::clamp`, which panics when `lo > hi`
+            (Function::Clamp, [x, lo, hi]) => Some(*x.max(lo).min(hi)),
+            (Function::Coc, [roll, skill]) => Some(coc_level(*roll, *skill)),
+            _ => None,
+        }
+    }
+
+    /// Apply on floats, `NaN` if the arguments don't match
+    pub(crate) fn float(&self, args: &[f64]) -> f64 {
+        match (self, args) {
+            (Function::Min, _) => args.iter().copied().fold(f64::INFINITY, f64::min),
+            (Function::Max, _) => args.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            (Function::Abs, [x]) => x.abs(),
+            (Function::Floor, [x]) => x.floor(),
+            (Function::Ceil, [x]) => x.ceil(),
+            // half away from zero
+            (Function::Round, [x]) => x.round(),
+            (Function::Clamp, [x, lo, hi]) => x.max(*lo).min(*hi),
+            (Function::Coc, [roll, skill]) => coc_level(*roll as i64, *skill as i64) as f64,
+            _ => f64::NAN,
+        }
+    }
+}
+
+/// Faces of a dice
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Sides {
+    Number(u64),
+    /// Fudge dice with this amount of `-` faces and of `+` faces out of 6, like `dF.1`
+    Fudge(u64),
+    /// Listed faces as inclusive ranges, like `d{1,1,2,3,5,8}` or `d{0..9}`
+    Custom(Vec<RangeInclusive<i64>>),
+    /// Tens and units dice with extra tens dice, bonus ones when positive and penalty ones when negative
+    Percent(i64),
+}
+
+impl Sides {
+    /// Amount of faces
+    pub fn count(&self) -> u64 {
+        match self {
+            Sides::Number(n) => *n,
+            Sides::Fudge(_) => 6,
+            Sides::Percent(_) => 100,
+            Sides::Custom(v) => v.iter().fold(0, |acc: u64, r| {
+                acc.saturating_add(r.end().abs_diff(*r.start()).saturating_add(1))
+            }),
+        }
+    }
+
+    /// Value of the face at `index`, starting from 1
+    pub fn face(&self, index: u64) -> i64 {
+        match self {
+            Sides::Number(_) | Sides::Percent(_) => index as i64,
+            Sides::Fudge(n) if index <= *n => -1,
+            Sides::Fudge(n) if index > 6 - n => 1,
+            Sides::Fudge(_) => 0,
+            Sides::Custom(v) => {
+                let mut left = index - 1;
+                for r in v {
+                    let len = r.end().abs_diff(*r.start());
+                    if left <= len {
+                        return r.start().saturating_add_unsigned(left);
+                    }
+                    left -= len + 1;
+                }
+                unreachable!("no face at index {}", index)
+            }
+        }
+    }
+
+    /// Lowest and highest face values
+    pub fn bounds(&self) -> (i64, i64) {
+        match self {
+            Sides::Number(n) => (1, *n as i64),
+            Sides::Fudge(_) => (-1, 1),
+            Sides::Percent(_) => (1, 100),
+            Sides::Custom(v) => v.iter().fold((i64::MAX, i64::MIN), |(lo, hi), r| {
+                (lo.min(*r.start()), hi.max(*r.end()))
+            }),
+        }
+    }
+}
+
+/// Ranges separated by `,`, like `1..3,5`
+fn format_ranges<T: std::fmt::Display + PartialEq>(v: &[RangeInclusive<T>]) -> String {
+    v.iter()
+        .map(|r| {
+            if r.start() == r.end() {
+                r.start().to_string()
+            } else {
+                format!("{}..{}", r.start(), r.end())
+            }
+        })
+        .join(",")
+}
+
+/// Comparison of a comparison point
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareOp {
+    Eq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl CompareOp {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            CompareOp::Eq => "=",
+            CompareOp::Lt => "<",
+            CompareOp::Le => "<=",
+            CompareOp::Gt => ">",
+            CompareOp::Ge => ">=",
+        }
+    }
+}
+
+/// Comparison point of a modifier, like the `>=5` of `1d6!>=5`
+///
+/// Its number is written right after the comparison, with a space it's an operator instead:
+/// `1d6!=3` explodes on 3 while `1d6!= 3` compares the roll with 3, and `{2d6, 1d20}>10`
+/// counts the totals over 10 while `{2d6, 1d20} > 10` compares their sum with 10.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Compare {
+    pub op: CompareOp,
+    pub value: i64,
+}
+
+impl Compare {
+    pub fn new(op: CompareOp, value: i64) -> Self {
+        Self { op, value }
+    }
+
+    /// Check if a die face meets the comparison
+    pub fn matches(&self, face: i64) -> bool {
+        match self.op {
+            CompareOp::Eq => face == self.value,
+            CompareOp::Lt => face < self.value,
+            CompareOp::Le => face <= self.value,
+            CompareOp::Gt => face > self.value,
+            CompareOp::Ge => face >= self.value,
+        }
+    }
+}
+
+/// Dice modifier, applied in the order it was written
+///
+/// Each modifier works on the whole pool left by the ones before it, so `4d6!K3` keeps the
+/// 3 highest of the dice and their explosions, and `10d10r1t7` counts the dice of 7 or more
+/// once the 1s got rerolled. Dropped and rerolled dice are skipped by later modifiers, while
+/// compounded and penetrating rolls count as part of their dice. Targets come last and score
+/// the dice left.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Modifier {
+    /// Rolls one extra dice for each dice on the point
+    Explode(Compare),
+    /// Explodes on the highest face without a comparison point
+    IndefExplode(Option<Compare>),
+    /// Extra rolls add into the same die
+    Compound(Option<Compare>),
+    /// Extra rolls add into the same die, minus 1 each
+    Penetrate(Option<Compare>),
+    /// Replaces each dice on the point by a new roll
+    Reroll(Compare),
+    /// Rerolls until the dice is off the point
+    IndefReroll(Compare),
+    KeepHigh(u64),
+    KeepLow(u64),
+    DropHigh(u64),
+    DropLow(u64),
+    /// Keeps the middle dice, dropping one more high dice than low ones when uneven
+    KeepMiddle(u64),
+    /// Keeps the dice on the point
+    KeepIf(Compare),
+    /// Drops the dice on the point
+    DropIf(Compare),
+    /// Keeps the first dice of each value
+    Unique,
+    /// Sorts the dice shown, descending when `true`
+    Sort(bool),
+    /// Rolls a d6 wild die along, both exploding into themselves and only the highest kept
+    Wild,
+    /// Counts the hits of a Shadowrun pool and tells its glitches
+    Shadowrun,
+    /// Matches dice of equal value into sets at least this wide, counted as the total when `true`
+    Match(u64, bool),
+    Target(Compare),
+    /// Faces counted as a success, as inclusive ranges
+    TargetEnum(Vec<RangeInclusive<i64>>),
+    DoubleTarget(Compare),
+    Failure(Compare),
+}
+
+impl Modifier {
+    /// Comparison used for a bare number, like the `>=` of `t8`
+    pub fn default_op(&self) -> CompareOp {
+        match self {
+            Modifier::Reroll(_) | Modifier::IndefReroll(_) | Modifier::Failure(_) => CompareOp::Le,
+            _ => CompareOp::Ge,
+        }
+    }
+}
+
+/// A dice term, like `4d6K3`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dice {
+    pub amount: u64,
+    pub sides: Sides,
+    pub modifiers: Vec<Modifier>,
+}
+
+/// A roll expression with its position in the query
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+
+    /// Binding power of the outermost operator, for parentheses when displayed
+    fn precedence(&self) -> u8 {
+        match &self.kind {
+            ExprKind::Binary(_, op, _) => op.precedence(),
+            ExprKind::Neg(_) => 4,
+            ExprKind::Integer(n) if *n < 0 => 4,
+            ExprKind::Float(n) if n.is_sign_negative() => 4,
+            _ => u8::MAX,
+        }
+    }
+
+    /// Display with parentheses if it binds looser than `min`
+    fn fmt_operand(&self, f: &mut std::fmt::Formatter<'_>, min: u8) -> std::fmt::Result {
+        match self.precedence() < min {
+            true => write!(f, "({self})"),
+            false => write!(f, "{self}"),
+        }
+    }
+}
+
+/// Kind of a roll expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprKind {
+    Integer(i64),
+    Float(f64),
+    Dice(Dice),
+    Block(Box<Expr>),
+    Neg(Box<Expr>),
+    Call(Function, Vec<Expr>),
+    Binary(Box<Expr>, Operator, Box<Expr>),
+    If(Box<Expr>, Box<Expr>, Box<Expr>),
+    /// Dice whose amount or sides are sub-expressions rolled first, like `(1d4)d6` or
+    /// `1d(2d6)`, the `Dice` holds the modifiers and what's written as a number
+    DynamicDice(Option<Box<Expr>>, Option<Box<Expr>>, Dice),
+    /// Expressions whose totals are kept, dropped or counted like dice, like `{4d6, 3d8}K1`
+    Group(Vec<Expr>, Vec<Modifier>),
+    /// Reference to a `let` binding
+    Variable(String),
+    /// Variable looked up by the host, like `@str_mod`
+    External(String),
+}
+
+/// How the results of a repeated expression are collected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RepeatMode {
+    List,
+    Sum,
+    Sort,
+}
+
+/// An expression rolled many times, like `(2d6 + 6) ^+ 8`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Repeat {
+    pub expr: Expr,
+    pub times: u64,
+    pub mode: RepeatMode,
+    pub span: Span,
+}
+
+/// Either a single, a repeated expression or a list of expressions
+#[derive(Debug, Clone, PartialEq)]
+pub enum Root {
+    Expr(Expr),
+    Repeat(Repeat),
+    /// Many expressions separated by `,`, rolled once each
+    List(Vec<Expr>),
+}
+
+impl Root {
+    pub fn span(&self) -> Span {
+        match self {
+            Root::Expr(expr) => expr.span,
+            Root::Repeat(repeat) => repeat.span,
+            Root::List(exprs) => match (exprs.first(), exprs.last()) {
+                (Some(first), Some(last)) => first.span.join(&last.span),
+                _ => Span::default(),
+            },
+        }
+    }
+}
+
+/// A sub-result rolled once and named, like `let atk = 1d20`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Binding {
+    pub name: String,
+    pub expr: Expr,
+    pub span: Span,
+}
+
+/// A whole parsed query, with its bindings and optional reason
+#[derive(Debug, Clone, PartialEq)]
+pub struct Command {
+    pub bindings: Vec<Binding>,
+    pub root: Root,
+    pub reason: Option<String>,
+}
+
+impl std::fmt::Display for Compare {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}", self.op.symbol(), self.value)
+    }
+}
+
+impl std::fmt::Display for Modifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // a bare number when the comparison is the default one
+        let point = |c: &Compare| {
+            if c.op == self.default_op() {
+                c.value.to_string()
+            } else {
+                c.to_string()
+            }
+        };
+        match self {
+            Modifier::Explode(c) => write!(f, "e{}", point(c)),
+            Modifier::IndefExplode(Some(c)) => write!(f, "!{}", point(c)),
+            Modifier::IndefExplode(None) => write!(f, "!"),
+            Modifier::Compound(Some(c)) => write!(f, "!!{}", point(c)),
+            Modifier::Compound(None) => write!(f, "!!"),
+            Modifier::Penetrate(Some(c)) => write!(f, "!p{}", point(c)),
+            Modifier::Penetrate(None) => write!(f, "!p"),
+            Modifier::Reroll(c) => write!(f, "r{}", point(c)),
+            Modifier::IndefReroll(c) => write!(f, "ir{}", point(c)),
+            Modifier::KeepHigh(n) => write!(f, "K{n}"),
+            Modifier::KeepLow(n) => write!(f, "k{n}"),
+            Modifier::DropHigh(n) => write!(f, "D{n}"),
+            Modifier::DropLow(n) => write!(f, "d{n}"),
+            Modifier::KeepMiddle(n) => write!(f, "km{n}"),
+            Modifier::KeepIf(c) => write!(f, "k{c}"),
+            Modifier::DropIf(c) => write!(f, "d{c}"),
+            Modifier::Unique => write!(f, "u"),
+            Modifier::Sort(false) => write!(f, "s"),
+            Modifier::Sort(true) => write!(f, "sd"),
+            Modifier::Wild => write!(f, "w"),
+            Modifier::Shadowrun => write!(f, "sr"),
+            Modifier::Match(width, count) => {
+                write!(f, "m{}", if *count { "t" } else { "" })?;
+                if *width != 2 {
+                    write!(f, "{width}")?;
+                }
+                Ok(())
+            }
+            Modifier::Target(c) => write!(f, "t{}", point(c)),
+            Modifier::TargetEnum(v) => write!(f, "t[{}]", format_ranges(v)),
+            Modifier::DoubleTarget(c) => write!(f, "tt{}", point(c)),
+            Modifier::Failure(c) => write!(f, "f{}", point(c)),
+        }
+    }
+}
+
+impl std::fmt::Display for Sides {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Sides::Number(n) => write!(f, "{n}"),
+            Sides::Fudge(2) => write!(f, "F"),
+            Sides::Fudge(n) => write!(f, "F.{n}"),
+            Sides::Custom(v) => write!(f, "{{{}}}", format_ranges(v)),
+            Sides::Percent(0) => write!(f, "%"),
+            Sides::Percent(n) if *n > 0 => write!(f, "%b{n}"),
+            Sides::Percent(n) => write!(f, "%p{}", n.unsigned_abs()),
+        }
+    }
+}
+
+impl std::fmt::Display for Dice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}d{}", self.amount, self.sides)?;
+        self.modifiers.iter().try_for_each(|m| write!(f, "{m}"))
+    }
+}
+
+impl std::fmt::Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            ExprKind::Integer(n) => write!(f, "{n}"),
+            ExprKind::Float(n) => {
+                let n = format!("{n:.2}");
+                write!(f, "{}", n.strip_suffix('0').unwrap_or(&n))
+            }
+            ExprKind::Dice(dice) => write!(f, "{dice}"),
+            ExprKind::DynamicDice(amount, sides, dice) => {
+                match amount {
+                    Some(amount) => write!(f, "({amount})d")?,
+                    None => write!(f, "{}d", dice.amount)?,
+                }
+                match sides {
+                    Some(sides) => write!(f, "({sides})")?,
+                    None => write!(f, "{}", dice.sides)?,
+                }
+                dice.modifiers.iter().try_for_each(|m| write!(f, "{m}"))
+            }
+            ExprKind::Block(expr) => write!(f, "({expr})"),
+            ExprKind::Neg(expr) => {
+                // a negated operand that starts with `-` gets parentheses, `--` isn't a negation
+                write!(f, "-")?;
+                expr.fmt_operand(f, self.precedence() + 1)
+            }
+            ExprKind::Call(function, args) => {
+                write!(f, "{}({})", function.name(), args.iter().format(", "))
+            }
+            ExprKind::Binary(lhs, op, rhs) => {
+                // `**` is right associative, the others left associative
+                let (left, right) = match op {
+                    Operator::Pow => (op.precedence() + 1, op.precedence()),
+                    _ => (op.precedence(), op.precedence() + 1),
+                };
+                lhs.fmt_operand(f, left)?;
+                write!(f, " {} ", op.symbol())?;
+                rhs.fmt_operand(f, right)
+            }
+            ExprKind::If(cond, then, other) => write!(f, "if {cond} then {then} else {other}"),
+            ExprKind::Group(exprs, modifiers) => {
+                write!(f, "{{{}}}", exprs.iter().format(", "))?;
+                modifiers.iter().try_for_each(|m| write!(f, "{m}"))
+            }
+            ExprKind::Variable(name) => write!(f, "{name}"),
+            ExprKind::External(name) => write!(f, "@{name}"),
+        }
+    }
+}
+
+impl std::fmt::Display for Command {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for binding in &self.bindings {
+            write!(f, "let {} = {}; ", binding.name, binding.expr)?;
+        }
+        match &self.root {
+            Root::Expr(expr) => write!(f, "{expr}")?,
+            Root::List(exprs) => write!(f, "{}", exprs.iter().format(", "))?,
+            Root::Repeat(repeat) => {
+                let mode = match repeat.mode {
+                    RepeatMode::List => "",
+                    RepeatMode::Sum => "+",
+                    RepeatMode::Sort => "#",
+                };
+                write!(f, "({}) ^{} {}", repeat.expr, mode, repeat.times)?
+            }
+        }
+        if let Some(reason) = &self.reason {
+            write!(f, " : {reason}")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,842 @@
+use crate::ast;
+use crate::constant;
+use crate::dice;
+use crate::distribution;
+use crate::distribution::check_work;
+use crate::distribution::Distribution;
+use crate::error::Error;
+use crate::error::Result;
+use crate::limits::EvalLimits;
+use crate::roll::history::percent_value;
+use crate::roll::VariableResolver;
+use std::collections::BTreeMap;
+
+/// Values fixed for the `let` bindings, and the resolver for `@name` variables
+struct Env<'a> {
+    bound: Vec<(String, f64)>,
+    resolver: &'a dyn VariableResolver,
+}
+
+/// Operand of an exact computation, fractional values are kept like in `kind::Single`
+enum Operand {
+    Dist(Distribution),
+    /// Values that aren't all integers, sorted, with their probability
+    Real(Vec<(f64, f64)>),
+}
+
+impl Operand {
+    fn constant(value: f64) -> Result<Self> {
+        Self::from_reals(Vec::new(), vec![(value, 1.0)])
+    }
+
+    /// New from integer and fractional weighted values
+    fn from_reals(ints: Vec<(i64, f64)>, mut reals: Vec<(f64, f64)>) -> Result<Self> {
+        if reals.iter().any(|(v, _)| !v.is_finite()) {
+            return Err(overflow());
+        }
+        let (fractions, integers): (Vec<_>, Vec<_>) =
+            reals.drain(..).partition(|(v, _)| v.fract() != 0.0);
+        let mut weights = BTreeMap::new();
+        for (value, p) in ints.into_iter().chain(
+            integers
+                .into_iter()
+                .map(|(v, p)| constant::truncate(v).map(|v| (v, p)).ok_or_else(overflow))
+                .collect::<Result<Vec<_>>>()?,
+        ) {
+            *weights.entry(value).or_insert(0.0) += p;
+        }
+        if fractions.is_empty() {
+            return Distribution::from_weights(&weights).map(Operand::Dist);
+        }
+        let mut reals: Vec<(f64, f64)> = weights
+            .into_iter()
+            .map(|(v, p)| (v as f64, p))
+            .chain(fractions)
+            .collect();
+        if reals.len() as u64 > distribution::limits::MAX_SUPPORT {
+            return Err(too_complex());
+        }
+        reals.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+        reals.dedup_by(|next, prev| {
+            let same = next.0 == prev.0;
+            if same {
+                prev.1 += next.1;
+            }
+            same
+        });
+        Ok(Operand::Real(reals))
+    }
+
+    fn is_integer(&self) -> bool {
+        matches!(self, Operand::Dist(_))
+    }
+
+    fn reals(&self) -> Vec<(f64, f64)> {
+        match self {
+            Operand::Dist(d) => d.iter().map(|(v, p)| (v as f64, p)).collect(),
+            Operand::Real(r) => r.clone(),
+        }
+    }
+
+    fn ints(&self) -> Vec<(i64, f64)> {
+        match self {
+            Operand::Dist(d) => d.iter().collect(),
+            Operand::Real(_) => Vec::new(),
+        }
+    }
+
+    /// Check if `0` is a possible value
+    fn has_zero(&self) -> bool {
+        match self {
+            Operand::Dist(d) => d.probability(0) > 0.0,
+            Operand::Real(r) => r.iter().any(|(v, _)| *v == 0.0),
+        }
+    }
+
+    /// Check if a value below `0` is possible
+    fn has_negative(&self) -> bool {
+        match self {
+            Operand::Dist(d) => d.min() < 0,
+            Operand::Real(r) => r.iter().any(|(v, _)| *v < 0.0),
+        }
+    }
+
+    /// Probability of a nonzero value, for conditions
+    fn truth(&self) -> f64 {
+        match self {
+            Operand::Dist(d) => 1.0 - d.probability(0),
+            Operand::Real(r) => r.iter().filter(|(v, _)| *v != 0.0).map(|(_, p)| p).sum(),
+        }
+    }
+
+    /// Weighted mixture of operands, weights summing to 1
+    fn mix(operands: Vec<(Operand, f64)>) -> Result<Self> {
+        let mut ints = Vec::new();
+        let mut reals = Vec::new();
+        for (operand, weight) in operands {
+            match operand {
+                Operand::Dist(d) => ints.extend(d.iter().map(|(v, p)| (v, p * weight))),
+                Operand::Real(r) => reals.extend(r.into_iter().map(|(v, p)| (v, p * weight))),
+            }
+        }
+        Self::from_reals(ints, reals)
+    }
+
+    /// Truncate fractional values, like `kind::Single` does for its total
+    fn into_dist(self) -> Result<Distribution> {
+        match self {
+            Operand::Dist(d) => Ok(d),
+            Operand::Real(r) => {
+                let ints = r
+                    .into_iter()
+                    .map(|(v, p)| constant::truncate(v).map(|v| (v, p)).ok_or_else(overflow))
+                    .collect::<Result<Vec<_>>>()?;
+                Self::from_reals(ints, Vec::new())?.into_dist()
+            }
+        }
+    }
+
+    /// Distribution of `f(values)` with each operand independent
+    fn apply<I, F>(operands: &[Operand], int_op: I, float_op: F) -> Result<Self>
+    where
+        I: Fn(&[i64]) -> Option<i64>,
+        F: Fn(&[f64]) -> f64,
+    {
+        let all_integers = operands.iter().all(Operand::is_integer);
+        let values = operands.iter().map(Operand::reals).collect::<Vec<_>>();
+        let ints = operands.iter().map(Operand::ints).collect::<Vec<_>>();
+        check_combinations(
+            values
+                .iter()
+                .fold(1u64, |acc, v| acc.saturating_mul(v.len() as u64)),
+        )?;
+        if all_integers {
+            check_corners(&ints, &int_op)?;
+        }
+        let mut int_results = BTreeMap::new();
+        let mut real_results = Vec::new();
+        let mut indices = vec![0; operands.len()];
+        let mut args_int = vec![0; operands.len()];
+        let mut args_float = vec![0.0; operands.len()];
+        'outer: loop {
+            let mut p = 1.0;
+            for (i, &index) in indices.iter().enumerate() {
+                let (value, prob) = values[i][index];
+                args_float[i] = value;
+                if all_integers {
+                    args_int[i] = ints[i][index].0;
+                }
+                p *= prob;
+            }
+            match all_integers.then(|| int_op(&args_int)).flatten() {
+                Some(value) => *int_results.entry(value).or_insert(0.0) += p,
+                None => real_results.push((float_op(&args_float), p)),
+            }
+            if (int_results.len() + real_results.len()) as u64 > distribution::limits::MAX_SUPPORT {
+                return Err(too_complex());
+            }
+            for i in (0..indices.len()).rev() {
+                indices[i] += 1;
+                if indices[i] < values[i].len() {
+                    continue 'outer;
+                }
+                indices[i] = 0;
+            }
+            break;
+        }
+        Self::from_reals(int_results.into_iter().collect(), real_results)
+    }
+}
+
+/// Fail if enumerating every combination of the operand values would take too long
+fn check_combinations(combinations: u64) -> Result<()> {
+    if combinations > distribution::limits::MAX_COMBINATIONS {
+        Err(too_complex())
+    } else {
+        Ok(())
+    }
+}
+
+/// Fail early when the results on the lowest and highest operand values are already too far
+/// apart, as they are possible results the support holds at least that many values
+fn check_corners<I>(ints: &[Vec<(i64, f64)>], int_op: &I) -> Result<()>
+where
+    I: Fn(&[i64]) -> Option<i64>,
+{
+    let bounds = ints
+        .iter()
+        .map(|v| match (v.first(), v.last()) {
+            (Some(first), Some(last)) => Some([first.0, last.0]),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>();
+    let Some(bounds) = bounds else {
+        return Ok(());
+    };
+    let mut args = vec![0; bounds.len()];
+    let (mut min, mut max) = (i64::MAX, i64::MIN);
+    for corner in 0..1u64 << bounds.len() {
+        for (i, bound) in bounds.iter().enumerate() {
+            args[i] = bound[((corner >> i) & 1) as usize];
+        }
+        if let Some(value) = int_op(&args) {
+            min = min.min(value);
+            max = max.max(value);
+        }
+    }
+    if min <= max && max.abs_diff(min) >= distribution::limits::MAX_SUPPORT {
+        return Err(too_complex());
+    }
+    Ok(())
+}
+
+fn too_complex() -> Error {
+    Error::TooComplex {
+        span: ast::Span::default(),
+    }
+}
+
+fn overflow() -> Error {
+    Error::Overflow {
+        span: ast::Span::default(),
+    }
+}
+
+/// Sub probability measure used while summing over the possible pools
+#[derive(Clone)]
+struct Partial {
+    min: i64,
+    probs: Vec<f64>,
+}
+
+impl Partial {
+    fn add_scaled(&mut self, other: &Partial, offset: i64, weight: f64) {
+        let min = other.min + offset;
+        let max = min + other.probs.len() as i64 - 1;
+        if self.probs.is_empty() {
+            self.min = min;
+        }
+        if min < self.min {
+            let mut probs = vec![0.0; (self.min - min) as usize];
+            probs.append(&mut self.probs);
+            self.probs = probs;
+            self.min = min;
+        }
+        let len = (max - self.min + 1) as usize;
+        if len > self.probs.len() {
+            self.probs.resize(len, 0.0);
+        }
+        let start = (min - self.min) as usize;
+        other
+            .probs
+            .iter()
+            .enumerate()
+            .for_each(|(i, p)| self.probs[start + i] += p * weight);
+    }
+}
+
+/// Exact model of a dice pool, dice are independent so only one die is tracked
+struct Pool {
+    amount: u64,
+    /// Value of each face
+    values: Vec<i64>,
+    /// Probability of each face after rerolls, in the order of `values`
+    faces: Vec<f64>,
+    explode: Option<ast::Compare>,
+    /// Amount of dice to keep, from the highest if `true`
+    keep: Option<(bool, u64)>,
+    /// Faces left out by a keep or drop on a point, in the order of `values`
+    dropped: Vec<bool>,
+    score: dice::Modifier,
+    /// Modifiers must be written as rerolls, explode, keep or drop
+    stage: u8,
+}
+
+impl Pool {
+    fn new(amount: u64, sides: &ast::Sides) -> Self {
+        let count = sides.count();
+        Self {
+            amount,
+            values: (1..=count).map(|index| sides.face(index)).collect(),
+            faces: vec![1.0 / count as f64; count as usize],
+            explode: None,
+            keep: None,
+            dropped: vec![false; count as usize],
+            score: dice::Modifier::None,
+            stage: 0,
+        }
+    }
+
+    fn enter_stage(&mut self, stage: u8, repeatable: bool) -> Result<()> {
+        if stage < self.stage || (stage == self.stage && !repeatable) {
+            return Err(Error::not_exact(
+                "expected modifiers ordered as rerolls, explode, keep or drop",
+            ));
+        }
+        self.stage = stage;
+        Ok(())
+    }
+
+    fn reroll(&mut self, point: ast::Compare) {
+        let rerolled = self.rerolled(point);
+        let raw = 1.0 / self.values.len() as f64;
+        self.faces
+            .iter_mut()
+            .zip(&self.values)
+            .for_each(|(p, face)| {
+                let kept = if point.matches(*face) { 0.0 } else { *p };
+                *p = kept + rerolled * raw;
+            });
+    }
+
+    fn indef_reroll(&mut self, point: ast::Compare) -> Result<()> {
+        let left = self
+            .values
+            .iter()
+            .filter(|face| !point.matches(**face))
+            .count();
+        if left == 0 {
+            return Err(Error::not_exact("every face is rerolled forever"));
+        }
+        let rerolled = self.rerolled(point);
+        let raw = 1.0 / left as f64;
+        self.faces
+            .iter_mut()
+            .zip(&self.values)
+            .for_each(|(p, face)| {
+                *p = if point.matches(*face) {
+                    0.0
+                } else {
+                    *p + rerolled * raw
+                }
+            });
+        Ok(())
+    }
+
+    /// Probability of a face meeting the comparison
+    fn rerolled(&self, point: ast::Compare) -> f64 {
+        self.values
+            .iter()
+            .zip(self.faces.iter())
+            .filter(|(face, _)| point.matches(**face))
+            .map(|(_, p)| p)
+            .sum()
+    }
+
+    fn apply(&mut self, modifier: &ast::Modifier) -> Result<()> {
+        match *modifier {
+            ast::Modifier::Reroll(point) => {
+                self.enter_stage(0, true)?;
+                self.reroll(point);
+            }
+            ast::Modifier::IndefReroll(point) => {
+                self.enter_stage(0, true)?;
+                self.indef_reroll(point)?;
+            }
+            ast::Modifier::Explode(point) => {
+                self.enter_stage(1, false)?;
+                self.explode = Some(point);
+            }
+            ast::Modifier::IndefExplode(_) => {
+                return Err(Error::not_exact("indefinite explode"));
+            }
+            ast::Modifier::Compound(_) => {
+                return Err(Error::not_exact("compounding explode"));
+            }
+            ast::Modifier::Penetrate(_) => {
+                return Err(Error::not_exact("penetrating explode"));
+            }
+            ast::Modifier::KeepHigh(value)
+            | ast::Modifier::KeepLow(value)
+            | ast::Modifier::DropHigh(value)
+            | ast::Modifier::DropLow(value) => {
+                self.enter_stage(2, false)?;
+                if value > self.amount {
+                    return Err(Error::NotEnoughDiceToKeep {
+                        span: ast::Span::default(),
+                    });
+                }
+                self.keep = Some(match modifier {
+                    ast::Modifier::KeepHigh(_) => (true, value),
+                    ast::Modifier::KeepLow(_) => (false, value),
+                    ast::Modifier::DropHigh(_) => (false, self.amount - value),
+                    _ => (true, self.amount - value),
+                });
+            }
+            ast::Modifier::KeepIf(point) | ast::Modifier::DropIf(point) => {
+                self.enter_stage(2, false)?;
+                let keep = matches!(modifier, ast::Modifier::KeepIf(_));
+                self.dropped = self
+                    .values
+                    .iter()
+                    .map(|face| point.matches(*face) != keep)
+                    .collect();
+            }
+            ast::Modifier::KeepMiddle(_) => return Err(Error::not_exact("keep middle")),
+            ast::Modifier::Unique => return Err(Error::not_exact("unique dice")),
+            ast::Modifier::Wild => return Err(Error::not_exact("wild die")),
+            ast::Modifier::Shadowrun => self.score = dice::Modifier::hits(),
+            ast::Modifier::Sort(_) | ast::Modifier::Match(_, false) => (),
+            ast::Modifier::Match(_, true) => return Err(Error::not_exact("counting sets")),
+            ast::Modifier::Target(_)
+            | ast::Modifier::TargetEnum(_)
+            | ast::Modifier::DoubleTarget(_)
+            | ast::Modifier::Failure(_) => self.score = self.score.with_target(modifier),
+        }
+        Ok(())
+    }
+
+    /// Distribution of the score of a single die, explosions included
+    fn die(&self) -> Result<Distribution> {
+        let raw = 1.0 / self.values.len() as f64;
+        let mut weights = BTreeMap::new();
+        for ((&face, p), dropped) in self.values.iter().zip(&self.faces).zip(&self.dropped) {
+            let score = if *dropped { 0 } else { self.score.score(face) };
+            match self.explode {
+                Some(point) if point.matches(face) => self.values.iter().for_each(|&extra| {
+                    *weights
+                        .entry(score + self.score.score(extra))
+                        .or_insert(0.0) += p * raw
+                }),
+                _ => *weights.entry(score).or_insert(0.0) += p,
+            }
+        }
+        Distribution::from_weights(&weights)
+    }
+
+    /// Distribution of the score of the kept dice
+    fn kept(&self, high: bool, count: u64) -> Result<Distribution> {
+        let amount = self.amount as usize;
+        let count = count as usize;
+        let widest = self
+            .values
+            .iter()
+            .map(|face| self.score.score(*face).unsigned_abs())
+            .max()
+            .unwrap_or(0);
+        check_work(
+            (self.values.len() as u64)
+                .saturating_mul((self.amount + 1).pow(2) / 2)
+                .saturating_mul(widest.saturating_mul(count as u64) + 1),
+        )?;
+        let ln_fact = (0..=amount).fold(vec![0.0f64], |mut acc, i| {
+            if i > 0 {
+                acc.push(acc[i - 1] + (i as f64).ln());
+            }
+            acc
+        });
+        // faces by value, equal ones can come in any order
+        let mut faces: Vec<usize> = (0..self.values.len()).collect();
+        faces.sort_by_key(|&i| self.values[i]);
+        if high {
+            faces.reverse();
+        }
+        // dp[j] holds the kept score once `j` dice got assigned a face
+        let mut dp: Vec<Option<Partial>> = vec![None; amount + 1];
+        dp[0] = Some(Partial {
+            min: 0,
+            probs: vec![1.0],
+        });
+        for face in faces {
+            let p = self.faces[face];
+            if p == 0.0 {
+                continue;
+            }
+            let score = self.score.score(self.values[face]);
+            let mut next: Vec<Option<Partial>> = vec![None; amount + 1];
+            for (j, partial) in dp.iter().enumerate() {
+                let Some(partial) = partial else { continue };
+                let left = amount - j;
+                for c in 0..=left {
+                    let weight = if c == 0 {
+                        1.0
+                    } else {
+                        (ln_fact[left] - ln_fact[c] - ln_fact[left - c] + c as f64 * p.ln()).exp()
+                    };
+                    if weight == 0.0 {
+                        continue;
+                    }
+                    let kept = c.min(count.saturating_sub(j)) as i64;
+                    next[j + c]
+                        .get_or_insert_with(|| Partial {
+                            min: 0,
+                            probs: Vec::new(),
+                        })
+                        .add_scaled(partial, kept * score, weight);
+                }
+            }
+            dp = next;
+        }
+        let total = dp[amount]
+            .take()
+            .ok_or_else(|| Error::not_exact("empty distribution"))?;
+        let weights = total
+            .probs
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| **p > 0.0)
+            .map(|(i, p)| (total.min + i as i64, *p))
+            .collect();
+        Distribution::from_weights(&weights)
+    }
+
+    fn total(&self) -> Result<Distribution> {
+        let dropped = self.keep.is_some() || self.dropped.contains(&true);
+        match self.keep {
+            _ if dropped && self.explode.is_some() => {
+                Err(Error::not_exact("keep or drop on exploded dice"))
+            }
+            Some((high, count)) => self.kept(high, count),
+            None => self.die()?.repeat(self.amount),
+        }
+    }
+}
+
+/// Rough amount of steps to compute the distribution of a dice term, and of values in it
+fn roll_work(dice: &ast::Dice) -> (u64, u64) {
+    let faces = dice.sides.count();
+    let mut width = faces;
+    let mut keep = None;
+    for modifier in &dice.modifiers {
+        match *modifier {
+            ast::Modifier::Explode(_) => width = faces.saturating_mul(2),
+            ast::Modifier::KeepHigh(count) | ast::Modifier::KeepLow(count) => keep = Some(count),
+            ast::Modifier::DropHigh(count) | ast::Modifier::DropLow(count) => {
+                keep = Some(dice.amount.saturating_sub(count))
+            }
+            _ => (),
+        }
+    }
+    let kept = keep.unwrap_or(dice.amount);
+    let values = kept
+        .saturating_mul(width.saturating_sub(1))
+        .saturating_add(1);
+    let work = match keep {
+        // same as checked by `Pool::kept`
+        Some(count) => faces
+            .saturating_mul(dice.amount.saturating_add(1).saturating_pow(2) / 2)
+            .saturating_mul(faces.saturating_mul(count).saturating_add(1)),
+        None => distribution::repeat_work(width, dice.amount),
+    };
+    (work.saturating_add(values), values)
+}
+
+/// Represent an exact probability calculator
+pub(crate) struct Calculator;
+
+impl Calculator {
+    /// Check the amount and sides of the dice against the limits, like when rolling them
+    fn check_size(dice: &ast::Dice, span: ast::Span, limits: &EvalLimits) -> Result<()> {
+        if dice.amount > limits.max_dice_amount {
+            return Err(Error::TooManyDice {
+                span,
+                max: limits.max_dice_amount,
+            });
+        }
+        if dice.sides.count() > limits.max_dice_sides {
+            return Err(Error::TooManySides {
+                span,
+                max: limits.max_dice_sides,
+            });
+        }
+        Ok(())
+    }
+
+    fn calc_roll(dice: &ast::Dice, span: ast::Span, limits: &EvalLimits) -> Result<Distribution> {
+        Self::check_size(dice, span, limits)?;
+        let amount = dice.amount;
+        let sides = &dice.sides;
+        if let ast::Sides::Percent(extra) = *sides {
+            return Self::calc_percent(extra)
+                .and_then(|die| die.repeat(amount))
+                .map_err(|e| e.at(span));
+        }
+        let mut pool = Pool::new(amount, sides);
+        dice.modifiers
+            .iter()
+            .try_for_each(|modifier| pool.apply(modifier))
+            .and_then(|_| pool.total())
+            .map_err(|e| e.at(span))
+    }
+
+    /// Distribution of a percentile die, the best or worst tens die of `1 + |extra|` being kept
+    fn calc_percent(extra: i64) -> Result<Distribution> {
+        let count = extra.unsigned_abs().saturating_add(1);
+        check_work(count.saturating_mul(100))?;
+        let count = i32::try_from(count).map_err(|_| overflow())?;
+        let mut weights = BTreeMap::new();
+        for units in 0..10 {
+            let mut values = (0..10)
+                .map(|tens| percent_value(tens * 10, units))
+                .collect::<Vec<_>>();
+            values.sort_unstable();
+            if extra < 0 {
+                values.reverse();
+            }
+            // every tens die is the `i`-th best or worse, but not all of them are worse
+            for (i, value) in values.into_iter().enumerate() {
+                let p = ((10 - i) as f64 / 10.0).powi(count) - ((9 - i) as f64 / 10.0).powi(count);
+                *weights.entry(value).or_insert(0.0) += p / 10.0;
+            }
+        }
+        Distribution::from_weights(&weights)
+    }
+
+    fn calc_infix(
+        op: ast::Operator,
+        lhs: Operand,
+        rhs: Operand,
+        divisor: ast::Span,
+    ) -> Result<Operand> {
+        if matches!(op, ast::Operator::Div | ast::Operator::Rem) && rhs.has_zero() {
+            return Err(Error::DivideByZero { span: divisor });
+        }
+        // a negative power of zero divides by zero, the whole operation gets the span
+        if op == ast::Operator::Pow && lhs.has_zero() && rhs.has_negative() {
+            return Err(Error::DivideByZero {
+                span: ast::Span::default(),
+            });
+        }
+        match (op, lhs, rhs) {
+            (ast::Operator::Add, Operand::Dist(l), Operand::Dist(r)) => {
+                l.add(&r).map(Operand::Dist)
+            }
+            (ast::Operator::Sub, Operand::Dist(l), Operand::Dist(r)) => {
+                l.add(&r.neg()?).map(Operand::Dist)
+            }
+            (_, lhs, rhs) => Operand::apply(
+                &[lhs, rhs],
+                |args| op.int(args[0], args[1]),
+                |args| op.float(args[0], args[1]),
+            ),
+        }
+    }
+
+    fn calc_call(function: ast::Function, args: Vec<Operand>) -> Result<Operand> {
+        let int_op = |args: &[i64]| function.int(args);
+        let float_op = |args: &[f64]| function.float(args);
+        match function {
+            // associative, so pairwise to keep the work low
+            ast::Function::Min | ast::Function::Max => {
+                let mut args = args.into_iter();
+                let first = args.next().ok_or_else(overflow)?;
+                args.try_fold(first, |acc, arg| {
+                    Operand::apply(&[acc, arg], int_op, float_op)
+                })
+            }
+            _ => Operand::apply(&args, int_op, float_op),
+        }
+    }
+
+    fn calc_operand(expr: &ast::Expr, env: &Env, limits: &EvalLimits) -> Result<Operand> {
+        match &expr.kind {
+            ast::ExprKind::Integer(n) => Ok(Operand::Dist(Distribution::constant(*n))),
+            ast::ExprKind::Float(n) => Operand::constant(*n),
+            ast::ExprKind::Block(expr) => Self::calc_operand(expr, env, limits),
+            ast::ExprKind::Neg(inner) => match Self::calc_operand(inner, env, limits)? {
+                Operand::Dist(d) => d.neg().map(Operand::Dist),
+                Operand::Real(r) => Ok(Operand::Real(
+                    r.into_iter().rev().map(|(v, p)| (-v, p)).collect(),
+                )),
+            }
+            .map_err(|e| e.at(expr.span)),
+            ast::ExprKind::Call(function, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| Self::calc_operand(arg, env, limits))
+                    .collect::<Result<Vec<_>>>()?;
+                Self::calc_call(*function, args).map_err(|e| e.at(expr.span))
+            }
+            ast::ExprKind::Dice(dice) => {
+                Self::calc_roll(dice, expr.span, limits).map(Operand::Dist)
+            }
+            ast::ExprKind::DynamicDice(amount, sides, dice) => {
+                let counts = |count: &Option<Box<ast::Expr>>| -> Result<Vec<_>> {
+                    match count {
+                        Some(count) => Ok(Self::calc_operand(count, env, limits)?
+                            .into_dist()?
+                            .iter()
+                            .map(|(value, p)| (Some((value, count.span)), p))
+                            .collect()),
+                        None => Ok(vec![(None, 1.0)]),
+                    }
+                };
+                let amounts = counts(amount)?;
+                let sides = counts(sides)?;
+                // each amount and sides is a dice term of its own, all of them get mixed
+                let (mut work, mut support) = (0u64, 0u64);
+                let mut rolls = Vec::new();
+                for &(amount, p) in &amounts {
+                    for &(sides, q) in &sides {
+                        let dice = dice::sized(dice, amount, sides)?;
+                        Self::check_size(&dice, expr.span, limits)?;
+                        let (steps, values) = roll_work(&dice);
+                        work = work.saturating_add(steps);
+                        support = support.saturating_add(values);
+                        if support > distribution::limits::MAX_SUPPORT {
+                            return Err(too_complex().at(expr.span));
+                        }
+                        check_work(work).map_err(|e| e.at(expr.span))?;
+                        rolls.push((dice, p * q));
+                    }
+                }
+                let branches = rolls
+                    .into_iter()
+                    .map(|(dice, p)| {
+                        Ok((Operand::Dist(Self::calc_roll(&dice, expr.span, limits)?), p))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                Operand::mix(branches).map_err(|e| e.at(expr.span))
+            }
+            ast::ExprKind::Binary(lhs, op, rhs) => Self::calc_infix(
+                *op,
+                Self::calc_operand(lhs, env, limits)?,
+                Self::calc_operand(rhs, env, limits)?,
+                rhs.span,
+            )
+            .map_err(|e| e.at(expr.span)),
+            ast::ExprKind::Group(exprs, modifiers) => {
+                // keep and drop counts don't depend on the totals
+                dice::eval_group(&vec![0; exprs.len()], modifiers).map_err(|e| e.at(expr.span))?;
+                let operands = exprs
+                    .iter()
+                    .map(|expr| Self::calc_operand(expr, env, limits))
+                    .collect::<Result<Vec<_>>>()?;
+                let int_op = |totals: &[i64]| dice::eval_group(totals, modifiers).ok().map(|g| g.1);
+                Operand::apply(&operands, int_op, |totals| {
+                    let totals = totals.iter().map(|t| *t as i64).collect::<Vec<_>>();
+                    int_op(&totals).map_or(f64::NAN, |total| total as f64)
+                })
+                .map_err(|e| e.at(expr.span))
+            }
+            ast::ExprKind::If(cond, then, other) => {
+                let truth = Self::calc_operand(cond, env, limits)?.truth();
+                // a branch that's never taken is never evaluated, like when rolling
+                let branches = [(then, truth), (other, 1.0 - truth)]
+                    .into_iter()
+                    .filter(|(_, weight)| *weight > 0.0)
+                    .map(|(branch, weight)| Ok((Self::calc_operand(branch, env, limits)?, weight)))
+                    .collect::<Result<Vec<_>>>()?;
+                Operand::mix(branches).map_err(|e| e.at(expr.span))
+            }
+            ast::ExprKind::Variable(name) => {
+                match env.bound.iter().rev().find(|(n, _)| n == name) {
+                    Some((_, value)) => Operand::constant(*value),
+                    None => Err(Error::UnknownName {
+                        span: expr.span,
+                        name: name.clone(),
+                    }),
+                }
+            }
+            ast::ExprKind::External(name) => match env.resolver.resolve(name) {
+                Some(value) => Ok(Operand::Dist(Distribution::constant(value))),
+                None => Err(Error::UnknownVariable {
+                    span: expr.span,
+                    name: name.clone(),
+                }),
+            },
+        }
+    }
+
+    // compute the distribution of a root expression, with the bindings fixed by `env`
+    fn calc_root(root: &ast::Root, env: &Env, limits: &EvalLimits) -> Result<Operand> {
+        let repeat = match root {
+            ast::Root::Expr(expr) => return Self::calc_operand(expr, env, limits),
+            ast::Root::List(_) => return Err(Error::NoTotal { span: root.span() }),
+            ast::Root::Repeat(repeat) => repeat,
+        };
+        if repeat.times > limits.max_repeat {
+            return Err(Error::TooManyRepeats {
+                span: repeat.span,
+                max: limits.max_repeat,
+            });
+        }
+        match repeat.mode {
+            ast::RepeatMode::Sum => Self::calc_operand(&repeat.expr, env, limits)?
+                .into_dist()?
+                .repeat(repeat.times)
+                .map(Operand::Dist)
+                .map_err(|e| e.at(repeat.span)),
+            ast::RepeatMode::List | ast::RepeatMode::Sort => {
+                Err(Error::NoTotal { span: repeat.span })
+            }
+        }
+    }
+
+    // compute the distribution for each value of the first binding, then mix them
+    fn calc_bound(
+        bindings: &[ast::Binding],
+        root: &ast::Root,
+        env: &mut Env,
+        work: u64,
+        limits: &EvalLimits,
+    ) -> Result<Operand> {
+        let Some((binding, rest)) = bindings.split_first() else {
+            return Self::calc_root(root, env, limits);
+        };
+        let values = Self::calc_operand(&binding.expr, env, limits)?.reals();
+        let work = work.saturating_mul(values.len() as u64);
+        check_work(work).map_err(|e| e.at(binding.span))?;
+        let mut branches = Vec::with_capacity(values.len());
+        for (value, p) in values {
+            env.bound.push((binding.name.clone(), value));
+            let branch = Self::calc_bound(rest, root, env, work, limits);
+            env.bound.pop();
+            branches.push((branch?, p));
+        }
+        Operand::mix(branches)
+    }
+
+    // compute the distribution of a whole command
+    pub(crate) fn calc_command(
+        command: &ast::Command,
+        resolver: &dyn VariableResolver,
+        limits: &EvalLimits,
+    ) -> Result<Distribution> {
+        let mut env = Env {
+            bound: Vec::new(),
+            resolver,
+        };
+        Self::calc_bound(&command.bindings, &command.root, &mut env, 1, limits)?.into_dist()
+    }
+}
@@ -9,14 +9,16 @@ pub struct Climber {
 }
 
 impl Climber {
-    pub fn raise<'i, P, F, G, T>(&self, pairs: P, primary: F, infix: G) -> T
+    pub fn raise<'i, P, F, G, H, T>(&self, pairs: P, primary: F, prefix: H, infix: G) -> T
     where
         P: Iterator<Item = Pair<'i, parser::Rule>>,
         F: FnMut(Pair<'i, parser::Rule>) -> T,
         G: FnMut(T, Pair<'i, parser::Rule>, T) -> T + 'i,
+        H: FnMut(Pair<'i, parser::Rule>, T) -> T + 'i,
     {
         self.inner
             .map_primary(primary)
+            .map_prefix(prefix)
             .map_infix(infix)
             .parse(pairs)
     }
@@ -27,9 +29,18 @@ pub static C: Lazy<Climber> = Lazy::new(|| {
     use pest::pratt_parser::Op;
     Climber {
         inner: PrattParser::new()
-            .op(Op::infix(parser::Rule::add, Assoc::Left))
-            .op(Op::infix(parser::Rule::sub, Assoc::Left))
-            .op(Op::infix(parser::Rule::mul, Assoc::Left))
-            .op(Op::infix(parser::Rule::div, Assoc::Left)),
+            .op(Op::infix(parser::Rule::lt, Assoc::Left)
+                | Op::infix(parser::Rule::le, Assoc::Left)
+                | Op::infix(parser::Rule::gt, Assoc::Left)
+                | Op::infix(parser::Rule::ge, Assoc::Left)
+                | Op::infix(parser::Rule::eq, Assoc::Left)
+                | Op::infix(parser::Rule::ne, Assoc::Left))
+            .op(Op::infix(parser::Rule::add, Assoc::Left)
+                | Op::infix(parser::Rule::sub, Assoc::Left))
+            .op(Op::infix(parser::Rule::mul, Assoc::Left)
+                | Op::infix(parser::Rule::div, Assoc::Left)
+                | Op::infix(parser::Rule::rem, Assoc::Left))
+            .op(Op::prefix(parser::Rule::neg))
+            .op(Op::infix(parser::Rule::pow, Assoc::Right)),
     }
 });
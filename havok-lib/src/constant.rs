@@ -6,14 +6,26 @@ pub enum Constant {
 }
 
 impl Constant {
-    pub fn get_value(&self) -> i64 {
+    /// Truncated value, `None` if it doesn't fit in an integer
+    pub fn get_value(&self) -> Option<i64> {
         match *self {
-            Constant::Integer(n) => n,
-            Constant::Float(n) => n as i64,
+            Constant::Integer(n) => Some(n),
+            Constant::Float(n) => truncate(n),
         }
     }
 }
 
+/// Truncate a float total, `None` if it doesn't fit in an integer
+pub(crate) fn truncate(value: f64) -> Option<i64> {
+    let value = value.trunc();
+    // bounds are rounded to -2^63 and 2^63, results that overflowed may be rounded to them too
+    if value.is_finite() && value > i64::MIN as f64 && value < i64::MAX as f64 {
+        Some(value as i64)
+    } else {
+        None
+    }
+}
+
 impl std::fmt::Display for Constant {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match *self {
@@ -1,8 +1,11 @@
-pub(crate) mod modifier;
-
+use crate::ast;
+use crate::error::Error;
 use crate::parser;
 use pest::iterators::Pairs;
+use std::collections::HashSet;
 use std::ops::Deref;
+use std::ops::Range;
+use std::ops::RangeInclusive;
 
 /// Mark if a dice result is a critic
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -10,26 +13,60 @@ pub enum Critic {
     Max,
     Min,
     Not,
+    /// Critical failure of the whole roll, like snake eyes on a wild die roll
+    Fumble,
+}
+
+/// What the modifiers did to a dice result
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Status {
+    /// Left out by a keep or drop
+    pub dropped: bool,
+    /// Replaced by the dice rolled right after it
+    pub rerolled: bool,
+    /// Triggered the roll of an extra dice
+    pub exploded: bool,
+    /// Extra roll added into the dice before it
+    pub compounded: bool,
+    /// Extra roll added into the dice before it, minus 1
+    pub penetrated: bool,
+    pub success: bool,
+    pub failure: bool,
+    /// Rolled on the wild die
+    pub wild: bool,
 }
 
 /// Keep one dice result with critic marker
 #[derive(Debug, Clone, Copy)]
 pub struct Result {
-    pub value: u64,
+    pub value: i64,
     pub critic: Critic,
+    pub status: Status,
 }
 
 impl Result {
-    pub fn new(value: u64, sides: u64) -> Self {
+    /// New from a face value and the lowest and highest faces of the dice
+    pub fn new(value: i64, (lowest, highest): (i64, i64)) -> Self {
         Result {
             value,
             critic: match value {
-                v if v == sides => Critic::Max,
-                v if v == 1 => Critic::Min,
+                v if v == highest => Critic::Max,
+                v if v == lowest => Critic::Min,
                 _ => Critic::Not,
             },
+            status: Status::default(),
         }
     }
+
+    /// Check if the result counts toward the total
+    pub fn is_counted(&self) -> bool {
+        !self.status.dropped && !self.status.rerolled
+    }
+
+    /// Check if the result adds into the dice before it
+    pub fn is_added(&self) -> bool {
+        self.status.compounded || self.status.penetrated
+    }
 }
 
 impl PartialEq for Result {
@@ -53,13 +90,49 @@ impl Ord for Result {
 }
 
 impl Deref for Result {
-    type Target = u64;
+    type Target = i64;
 
     fn deref(&self) -> &Self::Target {
         &self.value
     }
 }
 
+/// Glitch of a Shadowrun pool, when more than half the dice show 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Glitch {
+    Glitch,
+    /// Glitch without any hit
+    Critical,
+}
+
+impl Glitch {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Glitch::Glitch => "glitch",
+            Glitch::Critical => "critical glitch",
+        }
+    }
+}
+
+impl std::fmt::Display for Glitch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Dice of equal value matched together, `width` dice showing `height`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Set {
+    pub width: usize,
+    pub height: i64,
+}
+
+impl std::fmt::Display for Set {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
 /// Iterator that lazily returns each dice in the query
 pub struct Iter<'a> {
     pub inner: Pairs<'a, parser::Rule>,
@@ -73,15 +146,255 @@ impl<'a> Iterator for Iter<'a> {
     }
 }
 
-/// Optional dice modifier with the amount of dices to keep or drop
+/// Optional dice modifier with the comparison points of a target
 #[derive(Clone, PartialEq)]
 pub enum Modifier {
-    Fudge,
-    KeepLow(usize),
-    DropLow(usize),
-    KeepHigh(usize),
-    DropHigh(usize),
-    None(parser::Rule),
-    TargetEnum(Vec<u64>),
-    TargetDoubleFailure(u64, u64, u64),
+    None,
+    TargetEnum(Vec<RangeInclusive<i64>>),
+    /// Comparison points for a success, a failure and a double success
+    TargetDoubleFailure(
+        Option<ast::Compare>,
+        Option<ast::Compare>,
+        Option<ast::Compare>,
+    ),
+    /// Target of a wild die roll, with a raise for each 4 over it
+    Raises(ast::Compare),
+}
+
+impl Modifier {
+    /// Same modifier with the target `modifier` added, other comparison points are kept
+    pub(crate) fn with_target(&self, modifier: &ast::Modifier) -> Self {
+        let (t, f, d) = match *self {
+            Modifier::TargetDoubleFailure(t, f, d) => (t, f, d),
+            _ => (None, None, None),
+        };
+        match *modifier {
+            ast::Modifier::TargetEnum(ref v) => Modifier::TargetEnum(v.clone()),
+            ast::Modifier::Target(c) => Modifier::TargetDoubleFailure(Some(c), f, d),
+            ast::Modifier::Failure(c) => Modifier::TargetDoubleFailure(t, Some(c), d),
+            ast::Modifier::DoubleTarget(c) => Modifier::TargetDoubleFailure(t, f, Some(c)),
+            _ => self.clone(),
+        }
+    }
+
+    /// Hits of a Shadowrun pool, the dice on 5 or 6
+    pub(crate) fn hits() -> Self {
+        Modifier::TargetDoubleFailure(Some(ast::Compare::new(ast::CompareOp::Ge, 5)), None, None)
+    }
+
+    /// Same modifier with a lone target counting raises, for wild die rolls
+    pub(crate) fn with_raises(self) -> Self {
+        match self {
+            Modifier::TargetDoubleFailure(Some(t), None, None) => Modifier::Raises(t),
+            modifier => modifier,
+        }
+    }
+
+    /// Amount a dice result adds to the total
+    pub(crate) fn score(&self, value: i64) -> i64 {
+        match self {
+            Modifier::TargetDoubleFailure(t, f, d) => {
+                let hit = |c: &Option<ast::Compare>| c.is_some_and(|c| c.matches(value));
+                if hit(d) {
+                    2
+                } else if hit(t) {
+                    1
+                } else if hit(f) {
+                    -1
+                } else {
+                    0
+                }
+            }
+            Modifier::TargetEnum(v) => v.iter().any(|r| r.contains(&value)).into(),
+            Modifier::Raises(c) if c.matches(value) => 1 + value.saturating_sub(c.value).max(0) / 4,
+            Modifier::Raises(_) => 0,
+            _ => value,
+        }
+    }
+}
+
+/// Each counted dice with the extra rolls added into it, as its place in the pool and its total
+pub(crate) fn units(pool: &[Result]) -> Vec<(Range<usize>, i64)> {
+    let mut units: Vec<(Range<usize>, i64)> = Vec::new();
+    for (i, result) in pool.iter().enumerate() {
+        if !result.is_counted() {
+            continue;
+        }
+        match units.last_mut() {
+            Some((range, total)) if result.is_added() && range.end == i => {
+                range.end = i + 1;
+                *total = total.saturating_add(result.value);
+            }
+            _ => units.push((i..i + 1, result.value)),
+        }
+    }
+    units
+}
+
+/// Places, among `len` values sorted from the lowest, kept by a keep or drop of a count
+fn kept(len: usize, modifier: &ast::Modifier) -> crate::error::Result<Range<usize>> {
+    let n = match *modifier {
+        ast::Modifier::KeepHigh(n)
+        | ast::Modifier::KeepLow(n)
+        | ast::Modifier::DropHigh(n)
+        | ast::Modifier::DropLow(n)
+        | ast::Modifier::KeepMiddle(n) => n as usize,
+        _ => unreachable!("{:?}", modifier),
+    };
+    if n > len {
+        return Err(Error::NotEnoughDiceToKeep {
+            span: ast::Span::default(),
+        });
+    }
+    Ok(match modifier {
+        ast::Modifier::KeepHigh(_) => len - n..len,
+        ast::Modifier::KeepLow(_) => 0..n,
+        ast::Modifier::DropHigh(_) => 0..len - n,
+        ast::Modifier::DropLow(_) => n..len,
+        _ => (len - n) / 2..(len - n) / 2 + n,
+    })
+}
+
+/// Places of the values left out by a keep or drop, ties are dropped in order
+fn select(values: &[i64], modifier: &ast::Modifier) -> crate::error::Result<Vec<usize>> {
+    let places = 0..values.len();
+    Ok(match *modifier {
+        ast::Modifier::KeepIf(c) => places.filter(|&i| !c.matches(values[i])).collect(),
+        ast::Modifier::DropIf(c) => places.filter(|&i| c.matches(values[i])).collect(),
+        ast::Modifier::Unique => {
+            let mut seen = HashSet::new();
+            places.filter(|&i| !seen.insert(values[i])).collect()
+        }
+        _ => {
+            let mut order = places.collect::<Vec<_>>();
+            order.sort_by_key(|&i| values[i]);
+            let kept = kept(order.len(), modifier)?;
+            order
+                .into_iter()
+                .enumerate()
+                .filter(|(rank, _)| !kept.contains(rank))
+                .map(|(_, i)| i)
+                .collect()
+        }
+    })
+}
+
+/// Mark the dice left out by a keep or drop
+pub(crate) fn keep(pool: &mut [Result], modifier: &ast::Modifier) -> crate::error::Result<()> {
+    let units = units(pool);
+    let totals = units.iter().map(|(_, total)| *total).collect::<Vec<_>>();
+    for i in select(&totals, modifier)? {
+        pool[units[i].0.clone()]
+            .iter_mut()
+            .for_each(|r| r.status.dropped = true);
+    }
+    Ok(())
+}
+
+/// Sets of at least `width` counted dice of equal value, widest and highest first
+pub(crate) fn match_sets(pool: &[Result], width: usize) -> Vec<Set> {
+    let mut sets: Vec<Set> = Vec::new();
+    for (_, total) in units(pool) {
+        match sets.iter_mut().find(|set| set.height == total) {
+            Some(set) => set.width += 1,
+            None => sets.push(Set {
+                width: 1,
+                height: total,
+            }),
+        }
+    }
+    sets.retain(|set| set.width >= width.max(1));
+    sets.sort_unstable_by_key(|set| std::cmp::Reverse((set.width, set.height)));
+    sets
+}
+
+/// Glitch of the counted dice when more than half of the `amount` thrown first rolled a 1,
+/// critical when none of them scores a hit
+pub(crate) fn glitch(pool: &[Result], modifier: &Modifier, amount: u64) -> Option<Glitch> {
+    let units = units(pool);
+    let ones = units.iter().filter(|(_, total)| *total == 1).count();
+    // extra dice of the Rule of Six add hits, but don't raise the amount of 1s needed
+    if (ones as u64).saturating_mul(2) <= amount {
+        None
+    } else if units.iter().any(|(_, total)| modifier.score(*total) > 0) {
+        Some(Glitch::Glitch)
+    } else {
+        Some(Glitch::Critical)
+    }
+}
+
+/// Total of the counted dice, marking each success and failure of a target
+pub(crate) fn score(pool: &mut [Result], modifier: &Modifier) -> Option<i64> {
+    units(pool)
+        .into_iter()
+        .try_fold(0i64, |acc, (range, total)| {
+            let score = modifier.score(total);
+            if *modifier != Modifier::None {
+                let status = &mut pool[range.start].status;
+                status.success = score > 0;
+                status.failure = score < 0;
+            }
+            acc.checked_add(score)
+        })
+}
+
+/// Same dice with the amount or sides rolled by sub-expressions, each with its span
+pub(crate) fn sized(
+    dice: &ast::Dice,
+    amount: Option<(i64, ast::Span)>,
+    sides: Option<(i64, ast::Span)>,
+) -> crate::error::Result<ast::Dice> {
+    let mut dice = dice.clone();
+    if let Some((value, span)) = amount {
+        dice.amount = u64::try_from(value).map_err(|_| Error::InvalidCount { span, value })?;
+    }
+    if let Some((value, span)) = sides {
+        dice.sides = match u64::try_from(value) {
+            Ok(sides) if sides > 0 => ast::Sides::Number(sides),
+            _ => return Err(Error::InvalidCount { span, value }),
+        };
+    }
+    Ok(dice)
+}
+
+/// Apply the modifiers of a group to the total of each of its expressions,
+/// returns what the modifiers did to each total and the total of the group
+pub(crate) fn eval_group(
+    totals: &[i64],
+    modifiers: &[ast::Modifier],
+) -> crate::error::Result<(Vec<Status>, i64)> {
+    let mut status = vec![Status::default(); totals.len()];
+    let mut score = Modifier::None;
+    for modifier in modifiers {
+        match modifier {
+            ast::Modifier::Target(_)
+            | ast::Modifier::TargetEnum(_)
+            | ast::Modifier::DoubleTarget(_)
+            | ast::Modifier::Failure(_) => score = score.with_target(modifier),
+            _ => {
+                let left = (0..totals.len())
+                    .filter(|&i| !status[i].dropped)
+                    .collect::<Vec<_>>();
+                let values = left.iter().map(|&i| totals[i]).collect::<Vec<_>>();
+                for i in select(&values, modifier)? {
+                    status[left[i]].dropped = true;
+                }
+            }
+        }
+    }
+    let mut total = 0i64;
+    for (&value, status) in totals.iter().zip(status.iter_mut()) {
+        if status.dropped {
+            continue;
+        }
+        let value = score.score(value);
+        if score != Modifier::None {
+            status.success = value > 0;
+            status.failure = value < 0;
+        }
+        total = total.checked_add(value).ok_or(Error::Overflow {
+            span: ast::Span::default(),
+        })?;
+    }
+    Ok((status, total))
 }
@@ -0,0 +1,237 @@
+use crate::ast::Span;
+use crate::error::Error;
+use crate::error::Result;
+use std::collections::BTreeMap;
+
+/// Exact probability mass function of a roll expression total
+#[derive(Debug, Clone, PartialEq)]
+pub struct Distribution {
+    /// Smallest value with a non zero probability
+    min: i64,
+    /// Probability of each value, starting from `min`
+    probs: Vec<f64>,
+}
+
+impl Distribution {
+    /// New with a single value that always happens
+    pub fn constant(value: i64) -> Self {
+        Self {
+            min: value,
+            probs: vec![1.0],
+        }
+    }
+
+    /// New from a map of weighted values, weights are normalized
+    pub(crate) fn from_weights(weights: &BTreeMap<i64, f64>) -> Result<Self> {
+        let (min, max) = match (weights.keys().next(), weights.keys().next_back()) {
+            (Some(&min), Some(&max)) => (min, max),
+            _ => return Err(Error::not_exact("empty distribution")),
+        };
+        let len = max.abs_diff(min) + 1;
+        if len > limits::MAX_SUPPORT {
+            return Err(Error::TooComplex {
+                span: Span::default(),
+            });
+        }
+        let total: f64 = weights.values().sum();
+        let mut probs = vec![0.0; len as usize];
+        weights
+            .iter()
+            .for_each(|(value, weight)| probs[value.abs_diff(min) as usize] += weight / total);
+        let mut result = Self { min, probs };
+        result.trim();
+        Ok(result)
+    }
+
+    /// Drop values with zero probability from both ends
+    fn trim(&mut self) {
+        let start = self.probs.iter().position(|p| *p > 0.0).unwrap_or(0);
+        let end = self
+            .probs
+            .iter()
+            .rposition(|p| *p > 0.0)
+            .map_or(self.probs.len(), |end| end + 1);
+        if start > 0 || end < self.probs.len() {
+            self.probs = self.probs[start..end.max(start)].to_vec();
+            self.min += start as i64;
+        }
+    }
+
+    /// Iterate on each possible value with its probability
+    pub fn iter(&self) -> impl Iterator<Item = (i64, f64)> + '_ {
+        self.probs
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| **p > 0.0)
+            .map(|(i, p)| (self.min + i as i64, *p))
+    }
+
+    /// Probability of getting exactly `value`
+    pub fn probability(&self, value: i64) -> f64 {
+        if value < self.min {
+            return 0.0;
+        }
+        self.probs
+            .get(value.abs_diff(self.min) as usize)
+            .copied()
+            .unwrap_or(0.0)
+    }
+
+    /// Probability of getting `value` or more
+    pub fn at_least(&self, value: i64) -> f64 {
+        self.iter()
+            .filter(|(v, _)| *v >= value)
+            .map(|(_, p)| p)
+            .sum()
+    }
+
+    /// Probability of getting `value` or less
+    pub fn at_most(&self, value: i64) -> f64 {
+        self.iter()
+            .filter(|(v, _)| *v <= value)
+            .map(|(_, p)| p)
+            .sum()
+    }
+
+    pub fn min(&self) -> i64 {
+        self.min
+    }
+
+    pub fn max(&self) -> i64 {
+        self.min + (self.probs.len() as i64 - 1)
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.iter().map(|(v, p)| v as f64 * p).sum()
+    }
+
+    pub fn variance(&self) -> f64 {
+        let mean = self.mean();
+        self.iter()
+            .map(|(v, p)| (v as f64 - mean).powi(2) * p)
+            .sum()
+    }
+
+    pub fn std_dev(&self) -> f64 {
+        self.variance().sqrt()
+    }
+
+    /// Smallest value whose cumulative probability reaches `percent` (0 to 100)
+    pub fn percentile(&self, percent: f64) -> i64 {
+        let target = percent.clamp(0.0, 100.0) / 100.0;
+        let mut cumulative = 0.0;
+        for (value, p) in self.iter() {
+            cumulative += p;
+            // tolerate rounding errors accumulated while summing
+            if cumulative >= target - 1e-12 {
+                return value;
+            }
+        }
+        self.max()
+    }
+
+    /// Amount of possible values, zero probability ones included
+    pub(crate) fn width(&self) -> u64 {
+        self.probs.len() as u64
+    }
+
+    /// Distribution of the sum of two independent distributions
+    pub(crate) fn add(&self, rhs: &Self) -> Result<Self> {
+        check_work(self.width().saturating_mul(rhs.width()))?;
+        let mut probs = vec![0.0; self.probs.len() + rhs.probs.len() - 1];
+        for (i, lp) in self.probs.iter().enumerate() {
+            if *lp == 0.0 {
+                continue;
+            }
+            for (j, rp) in rhs.probs.iter().enumerate() {
+                probs[i + j] += lp * rp;
+            }
+        }
+        // the max is checked too, so that every value fits
+        self.max().checked_add(rhs.max()).ok_or_else(overflow)?;
+        Ok(Self {
+            min: self.min.checked_add(rhs.min).ok_or_else(overflow)?,
+            probs,
+        })
+    }
+
+    /// Distribution of the value with its sign flipped
+    pub(crate) fn neg(&self) -> Result<Self> {
+        Ok(Self {
+            min: self.max().checked_neg().ok_or_else(overflow)?,
+            probs: self.probs.iter().rev().copied().collect(),
+        })
+    }
+
+    /// Distribution of the sum of `times` independent copies
+    pub(crate) fn repeat(&self, times: u64) -> Result<Self> {
+        let mut result = Self::constant(0);
+        let mut base = self.clone();
+        let mut times = times;
+        while times > 0 {
+            if times & 1 == 1 {
+                result = result.add(&base)?;
+            }
+            times >>= 1;
+            if times > 0 {
+                base = base.add(&base)?;
+            }
+        }
+        Ok(result)
+    }
+}
+
+impl std::fmt::Display for Distribution {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "*mean* **{:.2}** *std dev* **{:.2}** *min* **{}** *max* **{}**",
+            self.mean(),
+            self.std_dev(),
+            self.min(),
+            self.max()
+        )
+    }
+}
+
+pub(crate) mod limits {
+    /// Arbitrary limits to keep the exact computation cheap
+    pub(crate) const MAX_SUPPORT: u64 = 1_000_000;
+    pub(crate) const MAX_WORK: u64 = 50_000_000;
+    /// Combinations of operand values tried by an operator or a function
+    pub(crate) const MAX_COMBINATIONS: u64 = 5_000_000;
+}
+
+fn overflow() -> Error {
+    Error::Overflow {
+        span: Span::default(),
+    }
+}
+
+/// Steps taken by `Distribution::repeat` on a distribution of `width` values
+pub(crate) fn repeat_work(width: u64, times: u64) -> u64 {
+    let (mut result, mut base, mut times, mut work) = (1u64, width, times, 0u64);
+    while times > 0 {
+        if times & 1 == 1 {
+            work = work.saturating_add(result.saturating_mul(base));
+            result = result.saturating_add(base.saturating_sub(1));
+        }
+        times >>= 1;
+        if times > 0 {
+            work = work.saturating_add(base.saturating_mul(base));
+            base = base.saturating_mul(2).saturating_sub(1);
+        }
+    }
+    work
+}
+
+/// Fail if an operation would take too many steps
+pub(crate) fn check_work(work: u64) -> Result<()> {
+    if work > limits::MAX_WORK {
+        Err(Error::TooComplex {
+            span: Span::default(),
+        })
+    } else {
+        Ok(())
+    }
+}
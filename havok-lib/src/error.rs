@@ -1,38 +1,250 @@
+use crate::ast::Function;
+use crate::ast::Span;
 use crate::parser;
+use itertools::Itertools;
+use pest::error::ErrorVariant;
+use pest::error::InputLocation;
 
-/// Crate Error type
-#[derive(Debug)]
+/// Crate Error type, each variant carries the span of the offending sub-expression
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Error {
-    Pest(Box<pest::error::Error<parser::Rule>>),
-    Other(String),
+    /// The query doesn't match the grammar
+    Parse { span: Span, expected: Vec<String> },
+    /// A number in the query doesn't fit in its type
+    NumberTooLarge { span: Span },
+    /// A function is called with the wrong amount of arguments
+    Arity { span: Span, function: Function },
+    /// A name is used before any `let` binding defines it
+    UnknownName { span: Span, name: String },
+    /// A `let` binding is named like a dice, so it can't be used
+    DiceName { span: Span, name: String },
+    /// The resolver has no value for a `@name` variable
+    UnknownVariable { span: Span, name: String },
+    /// A dice term throws more dices than allowed
+    TooManyDice { span: Span, max: u64 },
+    /// A dice term has more sides than allowed
+    TooManySides { span: Span, max: u64 },
+    /// A keep or drop modifier asks for more dices than thrown
+    NotEnoughDiceToKeep { span: Span },
+    /// The divisor is zero, or may be when computing a distribution
+    DivideByZero { span: Span },
+    /// An intermediate value doesn't fit in the total
+    Overflow { span: Span },
+    /// The query throws more dices than allowed, explosions and rerolls included
+    TooManyThrows { span: Span, max: u64 },
+    /// A dice explodes or is rerolled more times than allowed
+    TooDeep { span: Span, max: u64 },
+    /// An expression is repeated more times than allowed
+    TooManyRepeats { span: Span, max: u64 },
+    /// The history shown to the user holds more dice results than allowed
+    HistoryTooLong { span: Span, max: u64 },
+    /// A rolled amount of dices is negative, or rolled sides aren't positive
+    InvalidCount { span: Span, value: i64 },
+    /// The distribution of this sub-expression can't be computed exactly
+    NotExact { span: Span, reason: String },
+    /// The distribution of this sub-expression takes too many steps
+    TooComplex { span: Span },
+    /// A list, or a repeated expression without `^+`, has no single total
+    NoTotal { span: Span },
+    /// A simulation needs at least one sample
+    NoSamples,
+}
+
+impl Error {
+    /// Stable code identifying the error kind
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Parse { .. } => "E0001",
+            Self::NumberTooLarge { .. } => "E0002",
+            Self::Arity { .. } => "E0003",
+            Self::UnknownName { .. } => "E0004",
+            Self::UnknownVariable { .. } => "E0005",
+            Self::DiceName { .. } => "E0006",
+            Self::TooManyDice { .. } => "E0101",
+            Self::TooManySides { .. } => "E0102",
+            Self::NotEnoughDiceToKeep { .. } => "E0103",
+            Self::DivideByZero { .. } => "E0104",
+            Self::Overflow { .. } => "E0105",
+            Self::TooManyThrows { .. } => "E0106",
+            Self::TooDeep { .. } => "E0107",
+            Self::TooManyRepeats { .. } => "E0108",
+            Self::HistoryTooLong { .. } => "E0109",
+            Self::InvalidCount { .. } => "E0110",
+            Self::NotExact { .. } => "E0201",
+            Self::TooComplex { .. } => "E0202",
+            Self::NoTotal { .. } => "E0203",
+            Self::NoSamples => "E0204",
+        }
+    }
+
+    /// Byte span of the offending sub-expression, if any
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Self::Parse { span, .. }
+            | Self::NumberTooLarge { span }
+            | Self::Arity { span, .. }
+            | Self::UnknownName { span, .. }
+            | Self::UnknownVariable { span, .. }
+            | Self::DiceName { span, .. }
+            | Self::TooManyDice { span, .. }
+            | Self::TooManySides { span, .. }
+            | Self::NotEnoughDiceToKeep { span }
+            | Self::DivideByZero { span }
+            | Self::Overflow { span }
+            | Self::TooManyThrows { span, .. }
+            | Self::TooDeep { span, .. }
+            | Self::TooManyRepeats { span, .. }
+            | Self::HistoryTooLong { span, .. }
+            | Self::InvalidCount { span, .. }
+            | Self::NotExact { span, .. }
+            | Self::TooComplex { span }
+            | Self::NoTotal { span } => Some(*span),
+            Self::NoSamples => None,
+        }
+    }
+
+    /// Set the span if it's still unknown
+    pub(crate) fn at(mut self, at: Span) -> Self {
+        match &mut self {
+            Self::Parse { span, .. }
+            | Self::NumberTooLarge { span }
+            | Self::Arity { span, .. }
+            | Self::UnknownName { span, .. }
+            | Self::UnknownVariable { span, .. }
+            | Self::DiceName { span, .. }
+            | Self::TooManyDice { span, .. }
+            | Self::TooManySides { span, .. }
+            | Self::NotEnoughDiceToKeep { span }
+            | Self::DivideByZero { span }
+            | Self::Overflow { span }
+            | Self::TooManyThrows { span, .. }
+            | Self::TooDeep { span, .. }
+            | Self::TooManyRepeats { span, .. }
+            | Self::HistoryTooLong { span, .. }
+            | Self::InvalidCount { span, .. }
+            | Self::NotExact { span, .. }
+            | Self::TooComplex { span }
+            | Self::NoTotal { span } => {
+                if span.is_empty() {
+                    *span = at;
+                }
+            }
+            Self::NoSamples => (),
+        }
+        self
+    }
+
+    /// New distribution error without a known span yet
+    pub(crate) fn not_exact(reason: &str) -> Self {
+        Self::NotExact {
+            span: Span::default(),
+            reason: reason.to_owned(),
+        }
+    }
 }
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::Pest(e) => write!(f, "{e}"),
-            Self::Other(e) => write!(f, "{e}"),
+            Self::Parse { span, expected } if expected.is_empty() => {
+                write!(f, "unexpected input at position `{}`", span.start)
+            }
+            Self::Parse { span, expected } => write!(
+                f,
+                "expected {} at position `{}`",
+                expected.iter().format(", "),
+                span.start
+            ),
+            Self::NumberTooLarge { .. } => write!(f, "number too large"),
+            Self::Arity { function, .. } => {
+                write!(f, "`{}` expects {}", function.name(), function.arity())
+            }
+            Self::UnknownName { name, .. } => write!(f, "unknown name `{name}`"),
+            Self::UnknownVariable { name, .. } => write!(f, "unknown variable `@{name}`"),
+            Self::DiceName { name, .. } => {
+                write!(f, "`{name}` reads as a dice, it can't be a name")
+            }
+            Self::TooManyDice { max, .. } => {
+                write!(f, "exceeded max allowed amount of dices `{max}`")
+            }
+            Self::TooManySides { max, .. } => {
+                write!(f, "exceeded max allowed number of dice sides `{max}`")
+            }
+            Self::NotEnoughDiceToKeep { .. } => write!(f, "not enough dice to keep or drop"),
+            Self::DivideByZero { .. } => write!(f, "can't divide by zero"),
+            Self::Overflow { .. } => write!(f, "integer overflow"),
+            Self::TooManyThrows { max, .. } => {
+                write!(f, "exceeded max allowed amount of thrown dices `{max}`")
+            }
+            Self::TooDeep { max, .. } => {
+                write!(f, "exceeded max allowed explosions or rerolls `{max}`")
+            }
+            Self::TooManyRepeats { max, .. } => {
+                write!(f, "exceeded max allowed repetitions `{max}`")
+            }
+            Self::HistoryTooLong { max, .. } => {
+                write!(f, "exceeded max allowed history size `{max}`")
+            }
+            Self::InvalidCount { value, .. } => {
+                write!(f, "can't roll `{value}` as an amount of dices or sides")
+            }
+            Self::NotExact { reason, .. } => write!(f, "can't compute exactly, {reason}"),
+            Self::TooComplex { .. } => write!(f, "too complex to compute exactly"),
+            Self::NoTotal { .. } => write!(f, "no single total, only `^+` sums the results"),
+            Self::NoSamples => write!(f, "can't simulate without samples"),
         }
     }
 }
 
 impl std::error::Error for Error {}
 
-impl From<pest::error::Error<parser::Rule>> for Error {
-    fn from(value: pest::error::Error<parser::Rule>) -> Self {
-        Self::Pest(Box::new(value))
+/// Human readable name of a grammar rule
+fn describe(rule: &parser::Rule) -> String {
+    match rule {
+        parser::Rule::add => "`+`".to_owned(),
+        parser::Rule::sub => "`-`".to_owned(),
+        parser::Rule::mul => "`*`".to_owned(),
+        parser::Rule::div => "`/`".to_owned(),
+        parser::Rule::rem => "`%`".to_owned(),
+        parser::Rule::pow => "`**`".to_owned(),
+        parser::Rule::neg => "`-`".to_owned(),
+        parser::Rule::lt => "`<`".to_owned(),
+        parser::Rule::le => "`<=`".to_owned(),
+        parser::Rule::gt => "`>`".to_owned(),
+        parser::Rule::ge => "`>=`".to_owned(),
+        parser::Rule::eq => "`==`".to_owned(),
+        parser::Rule::ne => "`!=`".to_owned(),
+        parser::Rule::conditional => "`if`".to_owned(),
+        parser::Rule::binding => "`let`".to_owned(),
+        parser::Rule::external => "variable".to_owned(),
+        parser::Rule::name | parser::Rule::ident => "name".to_owned(),
+        parser::Rule::roll => "`d`".to_owned(),
+        parser::Rule::custom | parser::Rule::group => "`{`".to_owned(),
+        parser::Rule::face => "face".to_owned(),
+        parser::Rule::sort => "`#`".to_owned(),
+        parser::Rule::number | parser::Rule::nb_dice => "number".to_owned(),
+        parser::Rule::integer | parser::Rule::float => "constant".to_owned(),
+        parser::Rule::block_expr | parser::Rule::dynamic => "`(`".to_owned(),
+        parser::Rule::function | parser::Rule::func_name => "function".to_owned(),
+        parser::Rule::reason => "`:`".to_owned(),
+        parser::Rule::EOI => "end of input".to_owned(),
+        rule => format!("{rule:?}").replace('_', " "),
     }
 }
 
-impl From<&str> for Error {
-    fn from(value: &str) -> Self {
-        Self::Other(value.to_string())
-    }
-}
-
-impl From<String> for Error {
-    fn from(value: String) -> Self {
-        Self::Other(value)
+impl From<pest::error::Error<parser::Rule>> for Error {
+    fn from(value: pest::error::Error<parser::Rule>) -> Self {
+        let span = match value.location {
+            InputLocation::Pos(pos) => Span::new(pos, pos + 1),
+            InputLocation::Span((start, end)) => Span::new(start, end),
+        };
+        let expected = match value.variant {
+            ErrorVariant::ParsingError { positives, .. } => {
+                positives.iter().map(describe).unique().collect()
+            }
+            ErrorVariant::CustomError { message } => vec![message],
+        };
+        Self::Parse { span, expected }
     }
 }
 
@@ -1,369 +1,488 @@
-use crate::climber::C;
+use crate::ast;
 use crate::dice;
+use crate::error::Error;
 use crate::error::Result;
-use crate::parser::Parser;
-use crate::parser::Rule;
+use crate::limits::EvalLimits;
 use crate::roll;
+use crate::roll::history::percent_value;
+use crate::roll::history::Percentile;
 use crate::roll::kind;
-use pest::iterators::Pair;
-use pest::iterators::Pairs;
 
-mod limits {
-    /// Arbitrary limits to avoid oom
-    pub(crate) const MAX_DICE_AMOUNT: u64 = 5000;
-    pub(crate) const MAX_DICE_SIDES: u64 = 5000;
+/// How the extra rolls of an exploding dice are kept
+#[derive(Clone, Copy, PartialEq)]
+enum Extra {
+    /// One extra dice, that doesn't explode
+    Once,
+    /// Extra dice explode in turn
+    Indefinite,
+    /// Extra rolls add into the same dice
+    Compound,
+    /// Extra rolls add into the same dice, minus 1 each
+    Penetrate,
 }
 
-/// Represent an evaluator
-pub(crate) struct Evaluator;
+/// Represent an evaluator, keeping count of its work against the limits
+pub(crate) struct Evaluator<'a, S: roll::Source> {
+    source: &'a mut S,
+    resolver: &'a dyn roll::VariableResolver,
+    limits: &'a EvalLimits,
+    /// Dices thrown so far
+    thrown: u64,
+    /// Dice results added to histories so far
+    recorded: u64,
+    /// Results of the `let` bindings, in order
+    bound: Vec<(String, kind::Single)>,
+}
 
-impl Evaluator {
-    fn eval_explode<S: roll::Source>(
-        single: &mut kind::Single,
-        sides: u64,
-        results: Vec<dice::Result>,
-        pair: Pair<Rule>,
-        prior: &dice::Modifier,
-        source: &mut S,
-    ) -> (dice::Modifier, Vec<dice::Result>) {
-        let value = Parser::extract_modifier_value(pair).unwrap_or(sides);
-        let amount = results.iter().filter(|x| x.value >= value).count() as u64;
-        if prior != &dice::Modifier::None(Rule::explode)
-            && prior != &dice::Modifier::None(Rule::i_explode)
-        {
-            single.add_history(results.clone(), false);
+impl<'a, S: roll::Source> Evaluator<'a, S> {
+    pub(crate) fn new(
+        source: &'a mut S,
+        resolver: &'a dyn roll::VariableResolver,
+        limits: &'a EvalLimits,
+    ) -> Self {
+        Self {
+            source,
+            resolver,
+            limits,
+            thrown: 0,
+            recorded: 0,
+            bound: Vec::new(),
         }
-        let result = if amount > 0 {
-            let result = Self::roll(amount, sides, source);
-            single.add_history(result.clone(), false);
-            result
-        } else {
-            results
-        };
-        (dice::Modifier::None(Rule::explode), result)
     }
 
-    fn eval_indef_explode<S: roll::Source>(
-        single: &mut kind::Single,
-        sides: u64,
-        results: Vec<dice::Result>,
-        pair: Pair<Rule>,
-        prior: &dice::Modifier,
-        source: &mut S,
-    ) -> (dice::Modifier, Vec<dice::Result>) {
-        let value = Parser::extract_modifier_value(pair).unwrap_or(sides);
-        if prior != &dice::Modifier::None(Rule::explode)
-            && prior != &dice::Modifier::None(Rule::i_explode)
-        {
-            single.add_history(results.clone(), false);
-        }
-        let mut amount = results.into_iter().filter(|x| x.value >= value).count() as u64;
-        let mut results = Vec::new();
-        while amount > 0 {
-            results = Self::roll(amount, sides, source);
-            amount = results.iter().filter(|x| x.value >= value).count() as u64;
-            single.add_history(results.clone(), false);
-        }
-        (dice::Modifier::None(Rule::i_explode), results)
+    /// Roll a binding once, later references reuse its result
+    pub(crate) fn bind(&mut self, binding: &ast::Binding) -> Result<()> {
+        let single = self.eval(&binding.expr)?;
+        self.bound.push((binding.name.clone(), single));
+        Ok(())
     }
 
-    fn eval_reroll<S: roll::Source>(
-        single: &mut kind::Single,
-        sides: u64,
-        results: Vec<dice::Result>,
-        pair: Pair<Rule>,
-        source: &mut S,
-    ) -> (dice::Modifier, Vec<dice::Result>) {
-        let value = Parser::extract_modifier_value(pair).unwrap();
-        let mut has_rerolled = false;
-        let results: Vec<dice::Result> = results
-            .into_iter()
-            .map(|x| {
-                if x.value <= value {
-                    has_rerolled = true;
-                    Self::roll(1, sides, source)[0]
-                } else {
-                    x
-                }
-            })
-            .collect();
-        if has_rerolled {
-            single.add_history(results.clone(), false);
+    /// Results of the bindings, in order
+    pub(crate) fn into_bound(self) -> Vec<(String, kind::Single)> {
+        self.bound
+    }
+
+    /// Dices thrown so far
+    pub(crate) fn thrown(&self) -> u64 {
+        self.thrown
+    }
+
+    /// Count dice results added to histories
+    fn record(&mut self, amount: u64) -> Result<()> {
+        self.recorded += amount;
+        if self.recorded > self.limits.max_history {
+            return Err(Error::HistoryTooLong {
+                span: ast::Span::default(),
+                max: self.limits.max_history,
+            });
         }
-        (dice::Modifier::None(Rule::reroll), results)
+        Ok(())
     }
 
-    fn eval_indef_reroll<S: roll::Source>(
-        single: &mut kind::Single,
-        sides: u64,
-        results: Vec<dice::Result>,
-        pair: Pair<Rule>,
-        source: &mut S,
-    ) -> (dice::Modifier, Vec<dice::Result>) {
-        let value = Parser::extract_modifier_value(pair).unwrap();
-        let mut has_rerolled = false;
-        let result: Vec<dice::Result> = results
-            .into_iter()
-            .map(|x| {
-                let mut x = x;
-                while x.value <= value {
-                    has_rerolled = true;
-                    x = Self::roll(1, sides, source)[0]
-                }
-                x
+    /// Fail once a dice was thrown again more times than allowed
+    fn check_depth(&self, depth: u64) -> Result<()> {
+        if depth > self.limits.max_depth {
+            Err(Error::TooDeep {
+                span: ast::Span::default(),
+                max: self.limits.max_depth,
             })
-            .collect();
-        if has_rerolled {
-            single.add_history(result.clone(), false);
+        } else {
+            Ok(())
         }
-        (dice::Modifier::None(Rule::i_reroll), result)
     }
 
-    fn eval_modifier<S: roll::Source>(
-        single: &mut kind::Single,
-        sides: u64,
-        results: Vec<dice::Result>,
-        pair: Pair<Rule>,
-        source: &mut S,
-        prev: &dice::Modifier,
-    ) -> Result<dice::modifier::Result> {
-        let (modifier, mut results) = match &pair.as_rule() {
-            Rule::explode => Self::eval_explode(single, sides, results, pair, prev, source),
-            Rule::i_explode => Self::eval_indef_explode(single, sides, results, pair, prev, source),
-            Rule::reroll => Self::eval_reroll(single, sides, results, pair, source),
-            Rule::i_reroll => Self::eval_indef_reroll(single, sides, results, pair, source),
-            Rule::keep_hi => {
-                let value = Parser::extract_modifier_value(pair).unwrap();
-                if single.get_history().is_empty() {
-                    single.add_history(results.clone(), false);
-                }
-                (dice::Modifier::KeepHigh(value as usize), results)
+    /// Rebuild the pool, calling `f` after each counted dice and the rolls added into it
+    /// with the length and total of that dice
+    fn each_unit(
+        &mut self,
+        pool: Vec<dice::Result>,
+        mut f: impl FnMut(&mut Self, &mut Vec<dice::Result>, usize, i64) -> Result<()>,
+    ) -> Result<Vec<dice::Result>> {
+        let mut units = dice::units(&pool).into_iter().peekable();
+        let mut rebuilt = Vec::with_capacity(pool.len());
+        for (i, result) in pool.into_iter().enumerate() {
+            rebuilt.push(result);
+            if let Some((range, total)) = units.next_if(|(range, _)| range.end == i + 1) {
+                f(self, &mut rebuilt, range.len(), total)?;
             }
-            Rule::keep_lo => {
-                let value = Parser::extract_modifier_value(pair).unwrap();
-                if single.get_history().is_empty() {
-                    single.add_history(results.clone(), false);
-                }
-                (dice::Modifier::KeepLow(value as usize), results)
+        }
+        Ok(rebuilt)
+    }
+
+    fn eval_explode(
+        &mut self,
+        pool: Vec<dice::Result>,
+        sides: &ast::Sides,
+        point: ast::Compare,
+        extra: Extra,
+    ) -> Result<Vec<dice::Result>> {
+        // extra dice come after the pool in throw order, added rolls stay with their dice
+        let added = matches!(extra, Extra::Compound | Extra::Penetrate);
+        let mut extras = Vec::new();
+        let mut pool = self.each_unit(pool, |this, pool, _, total| {
+            if !point.matches(total) {
+                return Ok(());
             }
-            Rule::drop_hi => {
-                let value = Parser::extract_modifier_value(pair).unwrap();
-                if single.get_history().is_empty() {
-                    single.add_history(results.clone(), false);
+            let mut chain: Vec<dice::Result> = Vec::new();
+            let mut depth = 0;
+            loop {
+                if extra != Extra::Once {
+                    depth += 1;
+                    this.check_depth(depth)?;
                 }
-                (dice::Modifier::DropHigh(value as usize), results)
-            }
-            Rule::drop_lo => {
-                let value = Parser::extract_modifier_value(pair).unwrap();
-                if single.get_history().is_empty() {
-                    single.add_history(results.clone(), false);
+                if let Some(last) = chain.last_mut().or(pool.last_mut()) {
+                    last.status.exploded = true;
                 }
-                (dice::Modifier::DropLow(value as usize), results)
-            }
-            Rule::target => {
-                let target = pair.into_inner().next().unwrap();
-                match target.as_rule() {
-                    Rule::number => (
-                        dice::Modifier::TargetDoubleFailure(
-                            target.as_str().parse::<u64>().unwrap(),
-                            0,
-                            0,
-                        ),
-                        results,
-                    ),
-                    Rule::target_enum => {
-                        let values = target.into_inner();
-                        let values: Vec<_> =
-                            values.map(|p| p.as_str().parse::<u64>().unwrap()).collect();
-                        (dice::Modifier::TargetEnum(values), results)
+                let mut result = this.roll(1, sides)?[0];
+                let raw = result.value;
+                match extra {
+                    Extra::Compound => result.status.compounded = true,
+                    Extra::Penetrate => {
+                        result.status.penetrated = true;
+                        result.value -= 1;
                     }
-                    _ => unreachable!(),
+                    Extra::Once | Extra::Indefinite => (),
+                }
+                chain.push(result);
+                if extra == Extra::Once || !point.matches(raw) {
+                    break;
                 }
             }
-            Rule::double_target => {
-                let value = Parser::extract_modifier_value(pair).unwrap();
-                (dice::Modifier::TargetDoubleFailure(0, 0, value), results)
+            match added {
+                true => pool.append(&mut chain),
+                false => extras.append(&mut chain),
             }
-            Rule::failure => {
-                let value = Parser::extract_modifier_value(pair).unwrap();
-                (dice::Modifier::TargetDoubleFailure(0, value, 0), results)
+            Ok(())
+        })?;
+        pool.append(&mut extras);
+        Ok(pool)
+    }
+
+    fn eval_reroll(
+        &mut self,
+        pool: Vec<dice::Result>,
+        sides: &ast::Sides,
+        point: ast::Compare,
+        indefinite: bool,
+    ) -> Result<Vec<dice::Result>> {
+        self.each_unit(pool, |this, pool, len, total| {
+            if !point.matches(total) {
+                return Ok(());
             }
-            _ => unreachable!("{:#?}", pair),
-        };
-        let number = match modifier {
-            dice::Modifier::KeepHigh(n) | dice::Modifier::KeepLow(n) => {
-                if n > results.len() {
-                    results.len()
-                } else {
-                    n
+            let start = pool.len() - len;
+            pool[start..]
+                .iter_mut()
+                .for_each(|r| r.status.rerolled = true);
+            let mut depth = 0;
+            loop {
+                if indefinite {
+                    depth += 1;
+                    this.check_depth(depth)?;
                 }
-            }
-            dice::Modifier::DropHigh(n) | dice::Modifier::DropLow(n) => {
-                if n > results.len() {
-                    0
-                } else {
-                    n
+                let result = this.roll(1, sides)?[0];
+                pool.push(result);
+                if !indefinite || !point.matches(result.value) {
+                    return Ok(());
+                }
+                if let Some(last) = pool.last_mut() {
+                    last.status.rerolled = true;
                 }
             }
-            dice::Modifier::None(_)
-            | dice::Modifier::TargetDoubleFailure(_, _, _)
-            | dice::Modifier::TargetEnum(_)
-            | dice::Modifier::Fudge => 0,
-        };
-        results.sort_unstable();
-        let results = match modifier {
-            dice::Modifier::KeepHigh(_) => results[results.len() - number..].to_vec(),
-            dice::Modifier::KeepLow(_) => results[..number].to_vec(),
-            dice::Modifier::DropHigh(_) => results[..results.len() - number].to_vec(),
-            dice::Modifier::DropLow(_) => results[number..].to_vec(),
-            dice::Modifier::None(_)
-            | dice::Modifier::TargetDoubleFailure(_, _, _)
-            | dice::Modifier::TargetEnum(_)
-            | dice::Modifier::Fudge => results,
-        };
-        Ok(dice::modifier::Result { results, modifier })
+        })
     }
 
-    fn eval_roll<S: roll::Source>(mut dice: Pairs<Rule>, source: &mut S) -> Result<kind::Single> {
-        let mut single = kind::Single::new();
-        let maybe_amount = dice.next().unwrap();
-        let amount = match maybe_amount.as_rule() {
-            Rule::nb_dice => {
-                dice.next(); // skip `d` token
-                let amount = maybe_amount.as_str().parse::<u64>().unwrap();
-                if amount > limits::MAX_DICE_AMOUNT {
-                    return Err(format!(
-                        "exceeded max allowed amount of dices `{}`",
-                        limits::MAX_DICE_AMOUNT
-                    )
-                    .into());
-                }
-                amount
-            }
-            Rule::roll => 1,
-            _ => unreachable!("{:?}", maybe_amount),
-        };
-        let pair = dice.next().unwrap();
-        let (sides, is_fudge) = match pair.as_rule() {
-            Rule::nb_dice => (pair.as_str().parse::<u64>().unwrap(), false),
-            Rule::fudge => (6, true),
-            _ => unreachable!("{:?}", pair),
-        };
-        if sides > limits::MAX_DICE_SIDES {
-            return Err(format!(
-                "exceeded max allowed number of dice sides `{}`",
-                limits::MAX_DICE_SIDES
-            )
-            .into());
+    /// Roll the wild die along the trait dice, keeping the highest, snake eyes are a fumble
+    fn eval_wild(
+        &mut self,
+        pool: Vec<dice::Result>,
+        sides: &ast::Sides,
+    ) -> Result<Vec<dice::Result>> {
+        let amount = dice::units(&pool).len() as u64;
+        let six = ast::Sides::Number(6);
+        let wild = self.roll(1, &six)?;
+        let snake_eyes = pool
+            .iter()
+            .chain(&wild)
+            .all(|r| r.is_counted() && r.value == 1);
+        let highest = |sides: &ast::Sides| ast::Compare::new(ast::CompareOp::Ge, sides.bounds().1);
+        let mut pool = self.eval_explode(pool, sides, highest(sides), Extra::Compound)?;
+        let wild = self.eval_explode(wild, &six, highest(&six), Extra::Compound)?;
+        pool.extend(wild.into_iter().map(|mut r| {
+            r.status.wild = true;
+            r
+        }));
+        if snake_eyes {
+            pool.iter_mut()
+                .for_each(|r| r.critic = dice::Critic::Fumble);
         }
-        let mut results = Self::roll(amount, sides, source);
-        let mut modifier = dice::Modifier::None(Rule::expr);
-        let mut maybe_modifier = dice.next();
-        if !is_fudge {
-            if maybe_modifier.is_some() {
-                while maybe_modifier.is_some() {
-                    let pair = maybe_modifier.unwrap();
-                    let modifier_result =
-                        Self::eval_modifier(&mut single, sides, results, pair, source, &modifier)?;
-                    results = modifier_result.results;
-                    modifier = match modifier_result.modifier {
-                        dice::Modifier::TargetDoubleFailure(t, f, d) => match modifier {
-                            dice::Modifier::TargetDoubleFailure(ot, of, od) => {
-                                if t > 0 {
-                                    dice::Modifier::TargetDoubleFailure(t, of, od)
-                                } else if f > 0 {
-                                    dice::Modifier::TargetDoubleFailure(ot, f, od)
-                                } else {
-                                    dice::Modifier::TargetDoubleFailure(ot, of, d)
-                                }
-                            }
-                            _ => {
-                                single.add_history(results.clone(), is_fudge);
-                                modifier_result.modifier
-                            }
-                        },
-                        dice::Modifier::TargetEnum(_) => {
-                            single.add_history(results.clone(), is_fudge);
-                            modifier_result.modifier
-                        }
-                        _ => modifier_result.modifier,
-                    };
-                    maybe_modifier = dice.next();
-                }
-            } else {
-                single.add_history(results, is_fudge);
+        dice::keep(&mut pool, &ast::Modifier::KeepHigh(amount))?;
+        Ok(pool)
+    }
+
+    /// Apply a modifier to the pool, a target only changes how the pool is scored while a sort
+    /// or a match changes how it is shown
+    fn eval_modifier(
+        &mut self,
+        mut pool: Vec<dice::Result>,
+        sides: &ast::Sides,
+        modifier: &ast::Modifier,
+        score: &mut dice::Modifier,
+    ) -> Result<Vec<dice::Result>> {
+        let highest = ast::Compare::new(ast::CompareOp::Ge, sides.bounds().1);
+        match *modifier {
+            ast::Modifier::Explode(point) => self.eval_explode(pool, sides, point, Extra::Once),
+            ast::Modifier::IndefExplode(point) => {
+                self.eval_explode(pool, sides, point.unwrap_or(highest), Extra::Indefinite)
+            }
+            ast::Modifier::Compound(point) => {
+                self.eval_explode(pool, sides, point.unwrap_or(highest), Extra::Compound)
             }
-            single.eval_total(modifier)?;
+            ast::Modifier::Penetrate(point) => {
+                self.eval_explode(pool, sides, point.unwrap_or(highest), Extra::Penetrate)
+            }
+            ast::Modifier::Reroll(point) => self.eval_reroll(pool, sides, point, false),
+            ast::Modifier::IndefReroll(point) => self.eval_reroll(pool, sides, point, true),
+            ast::Modifier::KeepHigh(_)
+            | ast::Modifier::KeepLow(_)
+            | ast::Modifier::DropHigh(_)
+            | ast::Modifier::DropLow(_)
+            | ast::Modifier::KeepMiddle(_)
+            | ast::Modifier::KeepIf(_)
+            | ast::Modifier::DropIf(_)
+            | ast::Modifier::Unique => {
+                dice::keep(&mut pool, modifier)?;
+                Ok(pool)
+            }
+            ast::Modifier::Wild => self.eval_wild(pool, sides),
+            ast::Modifier::Shadowrun => {
+                *score = dice::Modifier::hits();
+                Ok(pool)
+            }
+            ast::Modifier::Sort(_) | ast::Modifier::Match(_, _) => Ok(pool),
+            ast::Modifier::Target(_)
+            | ast::Modifier::TargetEnum(_)
+            | ast::Modifier::DoubleTarget(_)
+            | ast::Modifier::Failure(_) => {
+                *score = score.with_target(modifier);
+                Ok(pool)
+            }
+        }
+    }
+
+    fn eval_roll(&mut self, dice: &ast::Dice, span: ast::Span) -> Result<kind::Single> {
+        self.eval_dice(dice, span).map_err(|e| e.at(span))
+    }
+
+    fn eval_dice(&mut self, dice: &ast::Dice, span: ast::Span) -> Result<kind::Single> {
+        let mut single = kind::Single::new();
+        let amount = dice.amount;
+        if amount > self.limits.max_dice_amount {
+            return Err(Error::TooManyDice {
+                span,
+                max: self.limits.max_dice_amount,
+            });
+        }
+        let sides = &dice.sides;
+        if sides.count() > self.limits.max_dice_sides {
+            return Err(Error::TooManySides {
+                span,
+                max: self.limits.max_dice_sides,
+            });
+        }
+        if let ast::Sides::Percent(extra) = *sides {
+            let dice = self.roll_percent(amount, extra)?;
+            self.record(dice.iter().map(|p| p.tens.len() as u64 + 1).sum())?;
+            single.add_percent(dice);
+            single.eval_total(dice::Modifier::None)?;
+            return Ok(single);
+        }
+        let mut pool = self.roll(amount, sides)?;
+        let mut score = dice::Modifier::None;
+        for modifier in dice.modifiers.iter() {
+            pool = self.eval_modifier(pool, sides, modifier, &mut score)?;
+        }
+        self.record(pool.len() as u64)?;
+        if dice.modifiers.contains(&ast::Modifier::Wild) {
+            score = score.with_raises();
+        }
+        let fumble = pool.iter().any(|r| r.critic == dice::Critic::Fumble);
+        let glitch = if dice.modifiers.contains(&ast::Modifier::Shadowrun) {
+            dice::glitch(&pool, &score, amount)
         } else {
-            single.add_history(results, is_fudge);
-            single.eval_total(if is_fudge {
-                dice::Modifier::Fudge
-            } else {
-                dice::Modifier::None(Rule::expr)
-            })?;
+            None
+        };
+        let sets = dice.modifiers.iter().rev().find_map(|m| match *m {
+            ast::Modifier::Match(width, count) => {
+                Some((dice::match_sets(&pool, width as usize), count))
+            }
+            _ => None,
+        });
+        single.add_history(pool, matches!(sides, ast::Sides::Fudge(_)));
+        single.eval_total(score)?;
+        if let Some((sets, count)) = sets {
+            single.add_sets(sets, count);
+        }
+        if fumble {
+            single.add_outcome("critical failure");
+        }
+        if let Some(glitch) = glitch {
+            single.add_glitch(glitch);
+        }
+        let sort = dice.modifiers.iter().rev().find_map(|m| match m {
+            ast::Modifier::Sort(descending) => Some(*descending),
+            _ => None,
+        });
+        if let Some(descending) = sort {
+            single.sort_history(descending);
         }
         Ok(single)
     }
 
     // compute a whole roll expression
-    pub(crate) fn eval<S: roll::Source>(
-        expr: Pairs<Rule>,
-        source: &mut S,
-        is_block: bool,
-    ) -> Result<kind::Single> {
-        let result = C.raise(
-            expr,
-            |pair: Pair<Rule>| match pair.as_rule() {
-                Rule::integer => Ok(kind::Single::with_total(
-                    pair.as_str().replace(' ', "").parse::<i64>().unwrap(),
-                )),
-                Rule::float => Ok(kind::Single::with_float(
-                    pair.as_str().replace(' ', "").parse::<f64>().unwrap(),
-                )),
-                Rule::block_expr => {
-                    let expr = pair.into_inner().next().unwrap().into_inner();
-                    Self::eval(expr, source, true)
-                }
-                Rule::dice => Self::eval_roll(pair.into_inner(), source),
-                _ => unreachable!("{:#?}", pair),
-            },
-            |lhs: Result<kind::Single>, op: Pair<Rule>, rhs: Result<kind::Single>| match (lhs, rhs)
-            {
-                (Ok(lhs), Ok(rhs)) => match op.as_rule() {
-                    Rule::add => Ok(lhs + rhs),
-                    Rule::sub => Ok(lhs - rhs),
-                    Rule::mul => Ok(lhs * rhs),
-                    Rule::div => {
-                        if rhs.is_zero() {
-                            Err("can't divide by zero".into())
-                        } else {
-                            Ok(lhs / rhs)
-                        }
+    pub(crate) fn eval(&mut self, expr: &ast::Expr) -> Result<kind::Single> {
+        match &expr.kind {
+            ast::ExprKind::Integer(n) => Ok(kind::Single::with_total(*n)),
+            ast::ExprKind::Float(n) => {
+                kind::Single::with_float(*n).ok_or(Error::Overflow { span: expr.span })
+            }
+            ast::ExprKind::Block(expr) => {
+                let mut result = self.eval(expr)?;
+                result.add_parens();
+                Ok(result)
+            }
+            ast::ExprKind::Call(function, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.eval(arg))
+                    .collect::<Result<Vec<_>>>()?;
+                kind::Single::checked_call(*function, args)
+                    .ok_or(Error::Overflow { span: expr.span })
+            }
+            ast::ExprKind::Neg(inner) => self
+                .eval(inner)?
+                .checked_neg()
+                .ok_or(Error::Overflow { span: expr.span }),
+            ast::ExprKind::Dice(dice) => self.eval_roll(dice, expr.span),
+            ast::ExprKind::DynamicDice(amount, sides, dice) => {
+                let amount = amount.as_deref().map(|e| self.eval_count(e)).transpose()?;
+                let sides = sides.as_deref().map(|e| self.eval_count(e)).transpose()?;
+                let count = |count: &Option<(kind::Single, ast::Span)>| {
+                    count
+                        .as_ref()
+                        .map(|(single, span)| (single.get_total(), *span))
+                };
+                let dice = dice::sized(dice, count(&amount), count(&sides))?;
+                let roll = self.eval_roll(&dice, expr.span)?;
+                Ok(kind::Single::with_counts(
+                    amount.map(|(single, _)| single),
+                    sides.map(|(single, _)| single),
+                    &dice,
+                    roll,
+                ))
+            }
+            ast::ExprKind::Binary(lhs, op, rhs) => {
+                let divisor = rhs.span;
+                let lhs = self.eval(lhs)?;
+                let rhs = self.eval(rhs)?;
+                match op {
+                    ast::Operator::Add => lhs.checked_add(rhs),
+                    ast::Operator::Sub => lhs.checked_sub(rhs),
+                    ast::Operator::Mul => lhs.checked_mul(rhs),
+                    ast::Operator::Div | ast::Operator::Rem if rhs.is_zero() => {
+                        return Err(Error::DivideByZero { span: divisor });
                     }
-                    _ => unreachable!(),
-                },
-                (Err(error), _) => Err(error),
-                (_, Err(error)) => Err(error),
-            },
-        );
-        match result {
-            Ok(mut result) => {
-                if is_block {
-                    result.add_parens();
+                    ast::Operator::Pow if lhs.is_zero() && rhs.get_value() < 0.0 => {
+                        return Err(Error::DivideByZero { span: expr.span });
+                    }
+                    ast::Operator::Div => lhs.checked_div(rhs),
+                    ast::Operator::Rem => lhs.checked_rem(rhs),
+                    ast::Operator::Pow => lhs.checked_pow(rhs),
+                    _ => lhs.checked_compare(rhs, *op),
                 }
-                Ok(result)
+                .ok_or(Error::Overflow { span: expr.span })
             }
-            error @ Err(_) => error,
+            ast::ExprKind::If(cond, then, other) => {
+                let cond = self.eval(cond)?;
+                let taken = !cond.is_zero();
+                let branch = self.eval(if taken { then } else { other })?;
+                Ok(kind::Single::with_branch(cond, taken, branch))
+            }
+            ast::ExprKind::Group(exprs, modifiers) => {
+                let items = exprs
+                    .iter()
+                    .map(|expr| self.eval(expr))
+                    .collect::<Result<Vec<_>>>()?;
+                let totals = items
+                    .iter()
+                    .map(kind::Single::get_total)
+                    .collect::<Vec<_>>();
+                let (status, total) =
+                    dice::eval_group(&totals, modifiers).map_err(|e| e.at(expr.span))?;
+                Ok(kind::Single::with_group(items, &status, total))
+            }
+            ast::ExprKind::Variable(name) => self
+                .bound
+                .iter()
+                .rev()
+                .find(|(bound, _)| bound == name)
+                .map(|(bound, single)| single.named(bound))
+                .ok_or_else(|| Error::UnknownName {
+                    span: expr.span,
+                    name: name.clone(),
+                }),
+            ast::ExprKind::External(name) => self
+                .resolver
+                .resolve(name)
+                .map(|value| kind::Single::with_total(value).named(&format!("@{name}")))
+                .ok_or_else(|| Error::UnknownVariable {
+                    span: expr.span,
+                    name: name.clone(),
+                }),
         }
     }
 
-    pub(crate) fn roll<S: roll::Source>(
-        amount: u64,
-        sides: u64,
-        source: &mut S,
-    ) -> Vec<dice::Result> {
-        (0..amount)
-            .map(|_| dice::Result::new(source.throw(sides), sides))
-            .collect()
+    /// Roll the amount or sides of a dice, with the span it came from
+    fn eval_count(&mut self, expr: &ast::Expr) -> Result<(kind::Single, ast::Span)> {
+        Ok((self.eval(expr)?, expr.span))
+    }
+
+    /// Roll percentile dice, keeping the lowest tens die with bonus dice and the highest with penalty ones
+    fn roll_percent(&mut self, amount: u64, extra: i64) -> Result<Vec<Percentile>> {
+        let ten = ast::Sides::Number(10);
+        let mut dice = Vec::with_capacity(amount as usize);
+        for _ in 0..amount {
+            let units = self.roll(1, &ten)?[0].value % 10;
+            let tens = self
+                .roll(extra.unsigned_abs().saturating_add(1), &ten)?
+                .iter()
+                .map(|t| t.value % 10 * 10)
+                .collect::<Vec<_>>();
+            let values = tens.iter().map(|t| percent_value(*t, units));
+            let best = if extra >= 0 {
+                values.enumerate().min_by_key(|(_, v)| *v)
+            } else {
+                values.enumerate().max_by_key(|(_, v)| *v)
+            };
+            dice.push(Percentile {
+                kept: best.map_or(0, |(i, _)| i),
+                tens,
+                units,
+            });
+        }
+        Ok(dice)
+    }
+
+    fn roll(&mut self, amount: u64, sides: &ast::Sides) -> Result<Vec<dice::Result>> {
+        self.thrown += amount;
+        if self.thrown > self.limits.max_dice_thrown {
+            return Err(Error::TooManyThrows {
+                span: ast::Span::default(),
+                max: self.limits.max_dice_thrown,
+            });
+        }
+        let (count, bounds) = (sides.count(), sides.bounds());
+        Ok((0..amount)
+            .map(|_| dice::Result::new(sides.face(self.source.throw(count)), bounds))
+            .collect())
     }
 }
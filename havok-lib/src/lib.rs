@@ -1,17 +1,36 @@
+pub mod ast;
+mod calculator;
 mod climber;
 mod constant;
 pub mod dice;
+pub mod distribution;
 pub mod error;
 mod evaluator;
+pub mod limits;
 mod parser;
 pub mod roll;
+pub mod simulation;
 pub mod solver;
 
 #[cfg(test)]
+#[allow(clippy::assertions_on_constants)]
 mod tests {
+    use crate::ast;
+    use crate::dice::Critic;
+    use crate::dice::Glitch;
+    use crate::error::Error;
+    use crate::limits::EvalLimits;
+    use crate::roll;
+    use crate::roll::history::History;
+    use crate::roll::kind;
     use crate::roll::Kind;
     use crate::roll::Source;
+    use crate::solver::Probability;
+    use crate::solver::RandomSource;
     use crate::solver::Solver;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use std::collections::HashMap;
 
     pub struct Mock<'a, T: Iterator<Item = u64>> {
         pub generator: &'a mut T,
@@ -32,6 +51,21 @@ mod tests {
         }
     }
 
+    /// Solve `input` with the `mock` values as dice
+    fn solve(input: &str, mock: Vec<u64>) -> roll::Result {
+        Solver::new(input)
+            .unwrap()
+            .solve_with_source(&mut Mock {
+                generator: &mut mock.into_iter(),
+            })
+            .unwrap()
+    }
+
+    /// Solve `input` with the `mock` values as dice, expecting a single roll
+    fn roll(input: &str, mock: Vec<u64>) -> kind::Single {
+        solve(input, mock).as_single().unwrap().clone()
+    }
+
     #[test]
     fn get_repeat_test() {
         let solver = Solver::new("(2d6 + 6) ^ 8 : test").unwrap();
@@ -384,6 +418,1357 @@ mod tests {
         }
     }
 
+    fn assert_close(expected: f64, actual: f64) {
+        assert!(
+            (expected - actual).abs() < 1e-9,
+            "expected `{}` got `{}`",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn distribution_sum_test() {
+        let dist = Solver::new("2d6 + 1").unwrap().distribution().unwrap();
+        assert_eq!(3, dist.min());
+        assert_eq!(13, dist.max());
+        assert_close(6.0 / 36.0, dist.probability(8));
+        assert_close(8.0, dist.mean());
+        assert_close(35.0 / 6.0, dist.variance());
+        assert_eq!(8, dist.percentile(50.0));
+    }
+
+    #[test]
+    fn distribution_keep_test() {
+        let dist = Solver::new("4d6K3").unwrap().distribution().unwrap();
+        assert_eq!(3, dist.min());
+        assert_eq!(18, dist.max());
+        assert_close(21.0 / 1296.0, dist.probability(18));
+        assert_close(15869.0 / 1296.0, dist.mean());
+        let dist = Solver::new("2d20d1").unwrap().distribution().unwrap();
+        assert_close(39.0 / 400.0, dist.probability(20));
+        let dist = Solver::new("2d20k1").unwrap().distribution().unwrap();
+        assert_close(1.0 / 400.0, dist.probability(20));
+    }
+
+    #[test]
+    fn distribution_modifiers_test() {
+        let dist = Solver::new("1d6r1").unwrap().distribution().unwrap();
+        assert_close(5.0 / 6.0 * 4.0 + 1.0 / 6.0 * 3.5, dist.mean());
+        let dist = Solver::new("1d6ir2").unwrap().distribution().unwrap();
+        assert_close(0.25, dist.probability(6));
+        let dist = Solver::new("1d6e6").unwrap().distribution().unwrap();
+        assert_close(1.0 / 36.0, dist.probability(12));
+        let dist = Solver::new("10d10 t7").unwrap().distribution().unwrap();
+        assert_close(4.0, dist.mean());
+        let dist = Solver::new("4dF").unwrap().distribution().unwrap();
+        assert_eq!(-4, dist.min());
+        assert_close(0.0, dist.mean());
+        let dist = Solver::new("(1d6 + 1) ^+ 3")
+            .unwrap()
+            .distribution()
+            .unwrap();
+        assert_close(13.5, dist.mean());
+    }
+
+    #[test]
+    fn distribution_error_test() {
+        assert!(Solver::new("1d6!").unwrap().distribution().is_err());
+        assert!(Solver::new("(1d6) ^ 3").unwrap().distribution().is_err());
+        assert!(Solver::new("1d6 / (1d2 - 1)")
+            .unwrap()
+            .distribution()
+            .is_err());
+        assert!(Solver::new("4d6 K3 r1").unwrap().distribution().is_err());
+    }
+
+    #[test]
+    fn distribution_budget_test() {
+        for input in ["1d5000 * 1d5000", "(1d2500 + 1d2500) * (1d2500 + 1d2500)"] {
+            let start = std::time::Instant::now();
+            let error = Solver::new(input).unwrap().distribution().unwrap_err();
+            assert_eq!("E0202", error.code());
+            assert!(start.elapsed() < std::time::Duration::from_secs(1));
+        }
+        let dist = Solver::new("1d100 * 1d100")
+            .unwrap()
+            .distribution()
+            .unwrap();
+        assert_close(1.0 / 10000.0, dist.probability(10000));
+    }
+
+    #[test]
+    fn simulation_test() {
+        let solver = Solver::new("2d6").unwrap();
+        let mock = vec![1, 1, 6, 6, 3, 4, 2, 5];
+        let simulation = solver
+            .simulate(
+                4,
+                &mut Mock {
+                    generator: &mut mock.into_iter(),
+                },
+            )
+            .unwrap();
+        assert_eq!(4, simulation.samples());
+        assert_eq!(Some(2), simulation.min());
+        assert_eq!(Some(12), simulation.max());
+        assert_eq!(2, simulation.histogram()[&7]);
+        assert_close(7.0, simulation.mean());
+        assert_close(0.75, simulation.at_least(7));
+        assert_eq!(Some(7), simulation.percentile(50.0));
+        let (low, high) = simulation.confidence_interval(0.95);
+        assert!(low < 7.0 && 7.0 < high);
+    }
+
+    #[test]
+    fn simulation_indef_test() {
+        let solver = Solver::new("1d6!").unwrap();
+        assert!(solver.distribution().is_err());
+        let mut rng = StdRng::seed_from_u64(42);
+        let simulation = solver.simulate(2000, &mut RandomSource::new(&mut rng));
+        let simulation = simulation.unwrap();
+        let (low, high) = simulation.confidence_interval(0.999);
+        assert!(low < 4.2 && 4.2 < high);
+        let (low, high) = simulation.at_least_interval(7, 0.999);
+        assert!(low < 1.0 / 6.0 && 1.0 / 6.0 < high);
+        assert!(Solver::new("(1d6) ^ 2")
+            .unwrap()
+            .simulate(1, &mut RandomSource::new(&mut rng))
+            .is_err());
+    }
+
+    #[test]
+    fn probability_test() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut source = RandomSource::new(&mut rng);
+        let solver = Solver::new("2d6").unwrap();
+        let probability = solver.probability(100, &mut source).unwrap();
+        assert!(matches!(probability, Probability::Exact(_)));
+        assert!(probability
+            .to_string()
+            .starts_with("*exact* *mean* **7.00**"));
+        let solver = Solver::new("1d6!").unwrap();
+        let probability = solver.probability(100, &mut source).unwrap();
+        assert!(matches!(probability, Probability::Simulated(_)));
+        let solver = Solver::new("1d5000 * 1d5000").unwrap();
+        let probability = solver.probability(100, &mut source).unwrap();
+        assert!(matches!(probability, Probability::Simulated(_)));
+        let solver = Solver::new("1d6 + @str").unwrap();
+        let error = solver.probability(100, &mut source).unwrap_err();
+        assert_eq!("E0005", error.code());
+        let solver = Solver::new(&format!("1d6! + {}", i64::MAX)).unwrap();
+        let error = solver.probability(100, &mut source).unwrap_err();
+        assert_eq!("E0105", error.code());
+        let solver = Solver::new("(10d6) ^+ 10")
+            .unwrap()
+            .with_limits(EvalLimits {
+                max_simulated_thrown: 1000,
+                ..Default::default()
+            });
+        assert!(solver.simulate(10, &mut source).is_ok());
+        let error = solver.simulate(11, &mut source).unwrap_err();
+        assert_eq!(
+            Error::TooManyThrows {
+                span: ast::Span::new(1, 5),
+                max: 1000
+            },
+            error
+        );
+    }
+
+    #[test]
+    fn compile_test() {
+        assert!(Solver::new("2d6 +").unwrap().compile().is_err());
+        let solver = Solver::new("(4d6K3 + 2) * 1.5 : stats")
+            .unwrap()
+            .compile()
+            .unwrap();
+        let ast = solver.ast().unwrap();
+        assert_eq!(Some("stats".to_owned()), ast.reason);
+        let root = match &ast.root {
+            ast::Root::Expr(expr) => expr,
+            _ => unreachable!(),
+        };
+        assert_eq!(ast::Span::new(0, 17), root.span);
+        match &root.kind {
+            ast::ExprKind::Binary(lhs, ast::Operator::Mul, rhs) => {
+                assert_eq!(ast::ExprKind::Float(1.5), rhs.kind);
+                match &lhs.kind {
+                    ast::ExprKind::Block(block) => match &block.kind {
+                        ast::ExprKind::Binary(dice, ast::Operator::Add, _) => {
+                            assert_eq!(
+                                ast::ExprKind::Dice(ast::Dice {
+                                    amount: 4,
+                                    sides: ast::Sides::Number(6),
+                                    modifiers: vec![ast::Modifier::KeepHigh(3)],
+                                }),
+                                dice.kind
+                            );
+                            assert_eq!(ast::Span::new(1, 6), dice.span);
+                        }
+                        _ => unreachable!(),
+                    },
+                    _ => unreachable!(),
+                }
+            }
+            _ => unreachable!(),
+        }
+        assert_eq!("(4d6K3 + 2) * 1.5 : stats", ast.to_string());
+        let mock = vec![6, 6, 6, 1, 3, 3, 3, 3];
+        let mut source = Mock {
+            generator: &mut mock.into_iter(),
+        };
+        let result = solver.solve_with_source(&mut source).unwrap();
+        assert_eq!(30, result.as_single().unwrap().get_total());
+        let result = solver.solve_with_source(&mut source).unwrap();
+        assert_eq!(16, result.as_single().unwrap().get_total());
+    }
+
+    #[test]
+    fn from_ast_test() {
+        let mut command = Solver::new("(1d20 + 4) ^+ 2")
+            .unwrap()
+            .compile()
+            .unwrap()
+            .ast()
+            .unwrap()
+            .clone();
+        if let ast::Root::Repeat(repeat) = &mut command.root {
+            repeat.times = 3;
+        }
+        let solver = Solver::from_ast(command);
+        assert_eq!("(1d20 + 4) ^+ 3", solver.as_str());
+        let mock = vec![1, 2, 3];
+        let result = solver
+            .solve_with_source(&mut Mock {
+                generator: &mut mock.into_iter(),
+            })
+            .unwrap();
+        assert_eq!(Some(18), result.as_multi().unwrap().get_total());
+    }
+
+    #[test]
+    fn error_test() {
+        let solve = |input: &str| Solver::new(input).unwrap().solve().unwrap_err();
+        let error = solve("2d6 +");
+        assert_eq!("E0001", error.code());
+        assert_eq!(Some(ast::Span::new(5, 6)), error.span());
+        let error = solve("1d6 + 2 / (1 - 1)");
+        assert_eq!(
+            Error::DivideByZero {
+                span: ast::Span::new(10, 17)
+            },
+            error
+        );
+        assert_eq!(
+            "1d6 + 2 / (1 - 1)\n          ^^^^^^^",
+            error.span().unwrap().underline("1d6 + 2 / (1 - 1)")
+        );
+        let error = solve("1 + 0 ** -1");
+        assert_eq!(
+            Error::DivideByZero {
+                span: ast::Span::new(4, 11)
+            },
+            error
+        );
+        let error = Solver::new("1 + (1d2 - 1) ** -1d2")
+            .unwrap()
+            .distribution()
+            .unwrap_err();
+        assert_eq!(
+            Error::DivideByZero {
+                span: ast::Span::new(4, 21)
+            },
+            error
+        );
+        let error = solve("1 + 6000d6");
+        assert_eq!("E0101", error.code());
+        assert_eq!(Some(ast::Span::new(4, 10)), error.span());
+        let error = solve("2 * 2d6K3");
+        assert_eq!(
+            Error::NotEnoughDiceToKeep {
+                span: ast::Span::new(4, 9)
+            },
+            error
+        );
+        let error = Solver::new("2 + 1d6!").unwrap().distribution().unwrap_err();
+        assert_eq!("E0201", error.code());
+        assert_eq!(Some(ast::Span::new(4, 8)), error.span());
+        let error = Solver::new("(1d6) ^ 3")
+            .unwrap()
+            .distribution()
+            .unwrap_err();
+        assert_eq!("E0203", error.code());
+        let error = Solver::new("1d6")
+            .unwrap()
+            .simulate(
+                0,
+                &mut Mock {
+                    generator: &mut std::iter::empty(),
+                },
+            )
+            .unwrap_err();
+        assert_eq!(Error::NoSamples, error);
+    }
+
+    #[test]
+    fn number_too_large_test() {
+        let huge = "99999999999999999999";
+        let error = Solver::new(&format!("d{huge}"))
+            .unwrap()
+            .solve()
+            .unwrap_err();
+        assert_eq!(
+            Error::NumberTooLarge {
+                span: ast::Span::new(1, 21)
+            },
+            error
+        );
+        let float = format!("{}.5", "9".repeat(400));
+        [
+            format!("{huge}d6"),
+            format!("(1d6) ^ {huge}"),
+            format!("(1d6) ^+ {huge}"),
+            format!("1 + {huge}"),
+            format!("4d6K{huge}"),
+            format!("4d6t[1,{huge}]"),
+            format!("4d6!{huge}"),
+            float,
+        ]
+        .iter()
+        .for_each(|input| {
+            let error = Solver::new(input).unwrap().solve().unwrap_err();
+            assert_eq!("E0002", error.code(), "{input}");
+        });
+    }
+
+    #[test]
+    fn limits_test() {
+        let solve = |input: &str| Solver::new(input).unwrap().solve().unwrap_err();
+        let error = solve("2 + 1d6!1");
+        assert_eq!(
+            Error::TooDeep {
+                span: ast::Span::new(4, 9),
+                max: 1000
+            },
+            error
+        );
+        assert_eq!("E0107", solve("1d6ir6").code());
+        assert_eq!("E0106", solve("(5000d6) ^+ 30").code());
+        assert_eq!(
+            Error::TooManyRepeats {
+                span: ast::Span::new(0, 11),
+                max: 100
+            },
+            solve("(1d6) ^ 101")
+        );
+        let limits = EvalLimits {
+            max_history: 4,
+            ..Default::default()
+        };
+        let solver = Solver::new("4d6 + 1d6").unwrap().with_limits(limits);
+        assert_eq!("E0109", solver.solve().unwrap_err().code());
+        let solver = Solver::new("4d6").unwrap().with_limits(limits);
+        assert!(solver.solve().is_ok());
+        let solver = Solver::new("(1d6) ^+ 3").unwrap().with_limits(EvalLimits {
+            max_repeat: 2,
+            ..Default::default()
+        });
+        assert_eq!("E0108", solver.distribution().unwrap_err().code());
+    }
+
+    #[test]
+    fn overflow_test() {
+        let max = i64::MAX;
+        let min = i64::MIN;
+        let error = Solver::new(&format!("2 * ({max} + 1)"))
+            .unwrap()
+            .solve()
+            .unwrap_err();
+        assert_eq!(
+            Error::Overflow {
+                span: ast::Span::new(5, 28)
+            },
+            error
+        );
+        [
+            format!("{min} / -1"),
+            format!("{max} * 1.5"),
+            format!("1d6 - {min}"),
+            format!("({max}) ^+ 2"),
+            "99999999999999999999.5".to_owned(),
+            "1d6 + 99999999999999999999.5".to_owned(),
+        ]
+        .iter()
+        .for_each(|input| {
+            let solver = Solver::new(input).unwrap();
+            assert_eq!("E0105", solver.solve().unwrap_err().code(), "{input}");
+            assert_eq!(
+                "E0105",
+                solver.distribution().unwrap_err().code(),
+                "{input}"
+            );
+        });
+        let error = Solver::new("1 + 99999999999999999999.5")
+            .unwrap()
+            .solve()
+            .unwrap_err();
+        assert_eq!(
+            Error::Overflow {
+                span: ast::Span::new(4, 26)
+            },
+            error
+        );
+        let solver = Solver::new(&format!("{max} - 1d6 + 1")).unwrap();
+        assert!(solver.solve().is_ok());
+        assert_eq!(max, solver.distribution().unwrap().max());
+    }
+
+    #[test]
+    fn precedence_test() {
+        let total = |input: &str| {
+            Solver::new(input)
+                .unwrap()
+                .solve()
+                .unwrap()
+                .as_single()
+                .unwrap()
+                .get_total()
+        };
+        assert_eq!(7, total("3*5/2"));
+        assert_eq!(5, total("10 - 2 - 3"));
+        assert_eq!(2, total("12 / 3 / 2"));
+        assert_eq!(14, total("2 + 3 * 4"));
+        assert_eq!(512, total("2 ** 3 ** 2"));
+        assert_eq!(-7, total("1 - 2 ** 3"));
+        assert_eq!(1, total("7 % 3 * 1"));
+        assert_eq!(-1, total("-7 % 3"));
+        assert_eq!(0, total("2 ** -1"));
+        assert_eq!(-4, total("-(2) ** 2"));
+        assert_eq!(-4, total("-2 ** 2"));
+        assert_eq!(-4, total("- 2 ** 2"));
+        assert_eq!(4, total("(-2) ** 2"));
+        assert_eq!(-2, total("-1.5 ** 2"));
+        assert_eq!(-8, total("2 * -2 ** 2"));
+        assert_eq!(-6, total("-2 * 3"));
+        assert_eq!(3, total("1 - -2"));
+        let error = Solver::new("7 % 0").unwrap().solve().unwrap_err();
+        assert_eq!(
+            Error::DivideByZero {
+                span: ast::Span::new(4, 5)
+            },
+            error
+        );
+        let dist = Solver::new("3*5/2").unwrap().distribution().unwrap();
+        assert_eq!(7, dist.min());
+        let dist = Solver::new("1d6 % 2").unwrap().distribution().unwrap();
+        assert_close(0.5, dist.probability(1));
+    }
+
+    #[test]
+    fn neg_test() {
+        let solver = Solver::new("-1d4 + 2 * -(2d6)").unwrap().compile().unwrap();
+        assert_eq!("-1d4 + 2 * -(2d6)", solver.ast().unwrap().to_string());
+        let mock = vec![3, 5, 2];
+        let result = solver
+            .solve_with_source(&mut Mock {
+                generator: &mut mock.into_iter(),
+            })
+            .unwrap();
+        let single = result.as_single().unwrap();
+        assert_eq!(-17, single.get_total());
+        assert_eq!("`-[3] + 2 * -([5, 2])` = **-17**", single.to_string());
+        let dist = solver.distribution().unwrap();
+        assert_eq!(-28, dist.min());
+        assert_eq!(-5, dist.max());
+        assert_close(-16.5, dist.mean());
+    }
+
+    #[test]
+    fn function_test() {
+        let single = roll("max(1d20, 1d20) + 2", vec![7, 15]);
+        assert_eq!(17, single.get_total());
+        assert_eq!("`max([7], [15]) + 2` = **17**", single.to_string());
+        assert_eq!(4, roll("ceil(2d6 / 2)", vec![3, 4]).get_total());
+        assert_eq!(3, roll("floor(2d6 / 2)", vec![3, 4]).get_total());
+        assert_eq!(4, roll("round(2d6 / 2)", vec![3, 4]).get_total());
+        assert_eq!(3, roll("2d6 / 2", vec![3, 4]).get_total());
+        assert_eq!(7, roll("(2d6 / 2) * 2", vec![3, 4]).get_total());
+        assert_eq!(20, roll("clamp(1d20 + 5, 1, 20)", vec![18]).get_total());
+        assert_eq!(1, roll("clamp(5, 10, 1)", vec![]).get_total());
+        assert_eq!(3, roll("abs(-1d4)", vec![3]).get_total());
+        assert_eq!(2, roll("min(2.5, 3)", vec![]).get_total());
+        let error = Solver::new("1 + abs(1, 2)").unwrap().solve().unwrap_err();
+        assert_eq!(
+            Error::Arity {
+                span: ast::Span::new(4, 13),
+                function: ast::Function::Abs
+            },
+            error
+        );
+        assert_eq!("`abs` expects 1 argument", error.to_string());
+        let dist = Solver::new("max(1d20, 1d20)")
+            .unwrap()
+            .distribution()
+            .unwrap();
+        assert_close(13.825, dist.mean());
+        let dist = Solver::new("ceil(1d6 / 2)")
+            .unwrap()
+            .distribution()
+            .unwrap();
+        assert_close(1.0 / 3.0, dist.probability(2));
+        assert_eq!(3, dist.max());
+        let dist = Solver::new("min(1d6, 3.5)")
+            .unwrap()
+            .distribution()
+            .unwrap();
+        assert_close(4.0 / 6.0, dist.probability(3));
+        let dist = Solver::new("(1d6 / 2) * 2")
+            .unwrap()
+            .distribution()
+            .unwrap();
+        assert_close(3.5, dist.mean());
+    }
+
+    #[test]
+    fn conditional_test() {
+        let attack = "if 1d20+5 >= 15 then 2d6+3 else 0";
+        let single = roll(attack, vec![12, 3, 4]);
+        assert_eq!(10, single.get_total());
+        assert_eq!(
+            "`if [12] + 5 >= 15 then [3, 4] + 3` = **10**",
+            single.to_string()
+        );
+        let single = roll(attack, vec![2]);
+        assert_eq!(0, single.get_total());
+        assert_eq!("`if [2] + 5 >= 15 else 0` = **0**", single.to_string());
+        assert_eq!(1, roll("1d6 != 3", vec![4]).get_total());
+        assert_eq!(0, roll("1d6 != 3", vec![3]).get_total());
+        assert_eq!(1, roll("1 + 2 < 2 * 2", vec![]).get_total());
+        assert_eq!(1, roll("1d6! > 6", vec![6, 2]).get_total());
+        assert_eq!(
+            4,
+            roll("1 + if 2.5 == 2.5 then 3 else 4", vec![]).get_total()
+        );
+        assert_eq!(
+            "if 1d20 + 5 >= 15 then 2d6 + 3 else 0",
+            Solver::new(attack)
+                .unwrap()
+                .compile()
+                .unwrap()
+                .ast()
+                .unwrap()
+                .to_string()
+        );
+        let dist = Solver::new(attack).unwrap().distribution().unwrap();
+        assert_close(0.55, 1.0 - dist.probability(0));
+        assert_close(0.55 * 10.0, dist.mean());
+        let dist = Solver::new("if 1 then 1d6 else 1 / 0")
+            .unwrap()
+            .distribution()
+            .unwrap();
+        assert_close(3.5, dist.mean());
+    }
+
+    #[test]
+    fn binding_test() {
+        let input = "let atk = 1d20; atk + 5, atk >= 19 : attack";
+        let solver = Solver::new(input).unwrap().compile().unwrap();
+        let ast = solver.ast().unwrap();
+        assert_eq!(1, ast.bindings.len());
+        assert_eq!("atk", ast.bindings[0].name);
+        assert_eq!(ast::Span::new(0, 14), ast.bindings[0].span);
+        assert_eq!(input, ast.to_string());
+        let mock = vec![19];
+        let result = solver
+            .solve_with_source(&mut Mock {
+                generator: &mut mock.into_iter(),
+            })
+            .unwrap();
+        let multi = result.as_multi().unwrap();
+        assert_eq!(None, multi.get_total());
+        assert_eq!(
+            vec![24, 1],
+            multi.iter().map(|r| r.get_total()).collect::<Vec<_>>()
+        );
+        assert_eq!("atk", result.get_bindings()[0].0);
+        assert_eq!(
+            "*atk* `[19]` = **19**\n`atk(19) + 5` = **24**\n`atk(19) >= 19` = **1**\n*reason* `attack`",
+            result.to_string()
+        );
+        let mock = vec![3, 4];
+        let result = Solver::new("let dmg = 1d6; let crit = dmg * 2; crit + 1d4")
+            .unwrap()
+            .solve_with_source(&mut Mock {
+                generator: &mut mock.into_iter(),
+            })
+            .unwrap();
+        assert_eq!(10, result.as_single().unwrap().get_total());
+        let error = Solver::new("let a = 1d6; a + b")
+            .unwrap()
+            .solve()
+            .unwrap_err();
+        assert_eq!(
+            Error::UnknownName {
+                span: ast::Span::new(17, 18),
+                name: "b".to_owned()
+            },
+            error
+        );
+        assert!(Solver::new("let if = 1; 2").unwrap().compile().is_err());
+        ["df", "d6", "d1", "D20"].iter().for_each(|name| {
+            let input = format!("let {name} = 5; {name} + 1");
+            let error = Solver::new(&input).unwrap().compile().unwrap_err();
+            assert_eq!(
+                Error::DiceName {
+                    span: ast::Span::new(4, 4 + name.len()),
+                    name: name.to_string()
+                },
+                error
+            );
+        });
+        assert_eq!(
+            "E0001",
+            Solver::new("d0").unwrap().solve().unwrap_err().code()
+        );
+        assert_eq!(3, roll("let dex = 2; dex + 1", vec![]).get_total());
+        assert_eq!(6, roll("let d = 5; d + 1", vec![]).get_total());
+        let single = roll("let half = 1.5; half * 2", vec![]);
+        assert_eq!("half(1.5) * 2", single.to_string_history());
+        let dist = Solver::new("let a = 1d6; a + a")
+            .unwrap()
+            .distribution()
+            .unwrap();
+        assert_close(1.0 / 6.0, dist.probability(12));
+        assert_close(0.0, dist.probability(11));
+        let dist = Solver::new("let a = 1d20; if a >= 11 then a else 0")
+            .unwrap()
+            .distribution()
+            .unwrap();
+        assert_close(7.75, dist.mean());
+        let error = Solver::new("1d6, 1d8").unwrap().distribution().unwrap_err();
+        assert_eq!(
+            Error::NoTotal {
+                span: ast::Span::new(0, 8)
+            },
+            error
+        );
+    }
+
+    #[test]
+    fn resolver_test() {
+        let stats = HashMap::from([("str_mod".to_owned(), 3), ("prof".to_owned(), 2)]);
+        let solver = Solver::new("1d20 + @str_mod + @prof").unwrap();
+        let mock = vec![12];
+        let result = solver
+            .solve_with_resolver(
+                &mut Mock {
+                    generator: &mut mock.into_iter(),
+                },
+                &stats,
+            )
+            .unwrap();
+        assert_eq!(
+            "`[12] + @str_mod(3) + @prof(2)` = **17**",
+            result.as_single().unwrap().to_string()
+        );
+        let dist = solver.distribution_with_resolver(&stats).unwrap();
+        assert_close(15.5, dist.mean());
+        assert_eq!(
+            "1d20 + @str_mod + @prof",
+            solver.compile().unwrap().ast().unwrap().to_string()
+        );
+        let error = Solver::new("1d20 + @dex_mod")
+            .unwrap()
+            .solve_with_resolver(
+                &mut Mock {
+                    generator: &mut vec![5].into_iter(),
+                },
+                &stats,
+            )
+            .unwrap_err();
+        assert_eq!(
+            Error::UnknownVariable {
+                span: ast::Span::new(7, 15),
+                name: "dex_mod".to_owned()
+            },
+            error
+        );
+        assert_eq!("unknown variable `@dex_mod`", error.to_string());
+        assert!(Solver::new("@str_mod").unwrap().distribution().is_err());
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut source = RandomSource::new(&mut rng);
+        let solver = Solver::new("1d6! + @str_mod").unwrap();
+        let simulation = solver
+            .simulate_with_resolver(100, &mut source, &stats)
+            .unwrap();
+        assert!(simulation.min().unwrap() >= 4);
+        let probability = solver
+            .probability_with_resolver(100, &mut source, &stats)
+            .unwrap();
+        assert!(matches!(probability, Probability::Simulated(_)));
+        let solver = Solver::new("1d6 + @prof").unwrap();
+        let probability = solver
+            .probability_with_resolver(100, &mut source, &stats)
+            .unwrap();
+        assert!(matches!(probability, Probability::Exact(_)));
+        assert!(solver.simulate(100, &mut source).is_err());
+    }
+
+    #[test]
+    fn chain_explode_test() {
+        let single = roll("3d6!!", vec![6, 2, 6, 6, 3, 1]);
+        assert_eq!(24, single.get_total());
+        assert_eq!("`[6+6+3, 2, 6+1]` = **24**", single.to_string());
+        let single = roll("2d6!p", vec![6, 4, 6, 2]);
+        assert_eq!(16, single.get_total());
+        assert_eq!("`[6+6-1+2-1, 4]` = **16**", single.to_string());
+        let single = roll("3d6!!K2", vec![6, 5, 4, 3]);
+        assert_eq!(14, single.get_total());
+        let single = roll("3d6!!k1", vec![6, 5, 4, 3]);
+        assert_eq!(4, single.get_total());
+        assert_eq!(8, roll("2d6!!5", vec![5, 1, 2]).get_total());
+        assert_eq!(9, roll("1d6! + 1", vec![6, 2]).get_total());
+        let solver = Solver::new("4d6!!K3 + 2d10!p8").unwrap().compile().unwrap();
+        assert_eq!("4d6!!K3 + 2d10!p8", solver.ast().unwrap().to_string());
+        let error = solver.distribution().unwrap_err();
+        assert_eq!("E0201", error.code());
+        let error = Solver::new("1d6!!1")
+            .unwrap()
+            .solve_with_source(&mut Mock {
+                generator: &mut std::iter::repeat(1),
+            })
+            .unwrap_err();
+        assert_eq!(
+            Error::TooDeep {
+                span: ast::Span::new(0, 6),
+                max: EvalLimits::default().max_depth
+            },
+            error
+        );
+    }
+
+    #[test]
+    fn compare_point_test() {
+        assert_eq!(9, roll("2d6!=1", vec![1, 5, 3]).get_total());
+        assert_eq!(7, roll("1d6!=3", vec![3, 4]).get_total());
+        assert_eq!(0, roll("1d6!= 3", vec![3]).get_total());
+        assert_eq!(12, roll("1d6!>=5", vec![5, 6, 1]).get_total());
+        assert_eq!(1, roll("1d6!>= 5", vec![5]).get_total());
+        assert_eq!(17, roll("2d6!>=5", vec![5, 6, 2, 4]).get_total());
+        assert_eq!(9, roll("2d6r=2", vec![2, 3, 6]).get_total());
+        assert_eq!(8, roll("2d6r<3", vec![1, 2, 4, 4]).get_total());
+        assert_eq!(2, roll("4d10t>8", vec![9, 8, 10, 1]).get_total());
+        assert_eq!(-1, roll("4d10t>8f<=2", vec![9, 8, 2, 1]).get_total());
+        assert_eq!(3, roll("5d6t[1..3,6]", vec![1, 3, 4, 5, 6]).get_total());
+        assert_eq!(1, roll("1d6! > 5", vec![6, 1]).get_total());
+        let solver = Solver::new("3d6!=1r<3t[1..3,6] + 1d10e10t8f1")
+            .unwrap()
+            .compile()
+            .unwrap();
+        assert_eq!(
+            "3d6!=1r<3t[1..3,6] + 1d10e10t8f1",
+            solver.ast().unwrap().to_string()
+        );
+        let dist = Solver::new("1d6r=2").unwrap().distribution().unwrap();
+        assert_close(1.0 / 36.0, dist.probability(2));
+        assert_close(7.0 / 36.0, dist.probability(3));
+        let dist = Solver::new("1d6ir<3").unwrap().distribution().unwrap();
+        assert_close(0.25, dist.probability(6));
+        let dist = Solver::new("1d6e=1").unwrap().distribution().unwrap();
+        assert_close(7.0 / 36.0, dist.probability(2));
+        assert_close(0.0, dist.probability(1));
+        let dist = Solver::new("4d10t>8f<2").unwrap().distribution().unwrap();
+        assert_close(0.4, dist.mean());
+    }
+
+    #[test]
+    fn custom_faces_test() {
+        let single = roll("3d{1,1,2,3,5,8}", vec![6, 1, 4]);
+        assert_eq!(12, single.get_total());
+        assert_eq!("[8, 1, 3]", single.to_string_history());
+        assert_eq!(-2, roll("2d{-1,0,1}", vec![1, 1]).get_total());
+        assert_eq!(9, roll("1d{0..9}", vec![10]).get_total());
+        assert_eq!(3, roll("4d{-2..2}K2", vec![1, 2, 5, 4]).get_total());
+        assert_eq!(2, roll("3d{0..9}t>5", vec![7, 10, 2]).get_total());
+        let solver = Solver::new("2d{1,1,2,3,5,8} + 1d{9..0, -3}")
+            .unwrap()
+            .compile()
+            .unwrap();
+        assert_eq!(
+            "2d{1,1,2,3,5,8} + 1d{0..9,-3}",
+            solver.ast().unwrap().to_string()
+        );
+        let dist = Solver::new("1d{1,1,2}").unwrap().distribution().unwrap();
+        assert_close(2.0 / 3.0, dist.probability(1));
+        let dist = Solver::new("2d{0..9}").unwrap().distribution().unwrap();
+        assert_close(9.0, dist.mean());
+        let dist = Solver::new("3d{-1,0,1}K1").unwrap().distribution().unwrap();
+        assert_close(1.0 / 27.0, dist.probability(-1));
+        assert!(matches!(
+            Solver::new("1d{1..10000}").unwrap().solve(),
+            Err(Error::TooManySides { .. })
+        ));
+    }
+
+    #[test]
+    fn fudge_modifier_test() {
+        let single = roll("4dF K2", vec![6, 5, 1, 3]);
+        assert_eq!(2, single.get_total());
+        assert_eq!("[+, +, ~~-~~, ~~▢~~]", single.to_string_history());
+        assert_eq!(2, roll("4dF t1", vec![6, 1, 3, 5]).get_total());
+        assert_eq!(2, roll("4dFr-1", vec![1, 3, 4, 6, 5]).get_total());
+        assert_eq!(1, roll("2dF!", vec![6, 2, 5, 3]).get_total());
+        assert_eq!(0, roll("4dF.1", vec![1, 2, 5, 6]).get_total());
+        assert_eq!(2, roll("4dF.1", vec![1, 6, 6, 6]).get_total());
+        let solver = Solver::new("4dF.1K2 + 2dF.2").unwrap().compile().unwrap();
+        assert_eq!("4dF.1K2 + 2dF", solver.ast().unwrap().to_string());
+        let dist = Solver::new("1dF.1").unwrap().distribution().unwrap();
+        assert_close(4.0 / 6.0, dist.probability(0));
+        let dist = Solver::new("2dFK1").unwrap().distribution().unwrap();
+        assert_close(5.0 / 9.0, dist.probability(1));
+    }
+
+    #[test]
+    fn percentile_test() {
+        let single = roll("d%", vec![7, 4]);
+        assert_eq!(47, single.get_total());
+        assert_eq!("[40+7]", single.to_string_history());
+        assert_eq!(100, roll("d%", vec![10, 10]).get_total());
+        let single = roll("d100b1", vec![7, 7, 4]);
+        assert_eq!(47, single.get_total());
+        assert_eq!("[~~70~~/40+7]", single.to_string_history());
+        let single = roll("d100p2", vec![10, 10, 5, 2]);
+        assert_eq!(100, single.get_total());
+        assert_eq!("[00/~~50~~/~~20~~+0]", single.to_string_history());
+        let single = roll("coc(d%, 65)", vec![3, 1]);
+        assert_eq!(3, single.get_total());
+        assert_eq!("coc([10+3], 65) → extreme", single.to_string_history());
+        assert_eq!(4, roll("coc(1, 10)", vec![]).get_total());
+        assert_eq!(-1, roll("coc(97, 40)", vec![]).get_total());
+        assert_eq!(0, roll("coc(97, 60)", vec![]).get_total());
+        assert_eq!(2, roll("coc(30, 60)", vec![]).get_total());
+        assert_eq!(1, roll("coc(60, 60)", vec![]).get_total());
+        assert_eq!(-1, roll("coc(100, 99)", vec![]).get_total());
+        let solver = Solver::new("d100b1 + 2d%p2 + d%")
+            .unwrap()
+            .compile()
+            .unwrap();
+        assert_eq!("1d%b1 + 2d%p2 + 1d%", solver.ast().unwrap().to_string());
+        let dist = Solver::new("d%").unwrap().distribution().unwrap();
+        assert_close(0.01, dist.probability(100));
+        assert_close(50.5, dist.mean());
+        let dist = Solver::new("d%b1").unwrap().distribution().unwrap();
+        assert_close(0.019, dist.probability(1));
+        let dist = Solver::new("d%p1").unwrap().distribution().unwrap();
+        assert_close(0.019, dist.probability(100));
+        let dist = Solver::new("coc(d%, 50)").unwrap().distribution().unwrap();
+        assert_close(0.01, dist.probability(4));
+        assert_close(0.09, dist.probability(3));
+    }
+
+    #[test]
+    fn group_test() {
+        let single = roll("{4d6, 3d8, 1d20+2}K1", vec![1, 2, 3, 4, 8, 8, 8, 5]);
+        assert_eq!(24, single.get_total());
+        assert_eq!(
+            "{~~[1, 2, 3, 4]~~, [8, 8, 8], ~~[5] + 2~~}",
+            single.to_string_history()
+        );
+        assert_eq!(7, roll("{4d6, 1d20+2}k1", vec![1, 2, 3, 4, 5]).get_total());
+        assert_eq!(0, roll("{3d6+3, 1d20}>10", vec![1, 1, 1, 5]).get_total());
+        assert_eq!(1, roll("{3d6+3, 1d20} > 10", vec![1, 1, 1, 5]).get_total());
+        assert_eq!(1, roll("{3d6+3, 1d20}> 10", vec![1, 1, 1, 5]).get_total());
+        let single = roll("{3d6+3, 1d20}>10", vec![4, 4, 4, 15]);
+        assert_eq!(2, single.get_total());
+        assert_eq!("{[4, 4, 4] + 3✓, [15]✓}", single.to_string_history());
+        let single = roll("{1d6, 1d6, 1d6}d1t4f2", vec![2, 5, 1]);
+        assert_eq!(0, single.get_total());
+        assert_eq!("{[2]✗, [5]✓, ~~[1]~~}", single.to_string_history());
+        assert_eq!(2, roll("{1d6, 1d6, 1d6}d1t4", vec![2, 5, 4]).get_total());
+        assert_eq!(11, roll("{1d6, 1d6}", vec![5, 6]).get_total());
+        assert!(matches!(
+            Solver::new("{1d6, 1d6}K3").unwrap().solve(),
+            Err(Error::NotEnoughDiceToKeep { .. })
+        ));
+        let solver = Solver::new("{4d6, 1d20 + 2}K1>10")
+            .unwrap()
+            .compile()
+            .unwrap();
+        assert_eq!("{4d6, 1d20 + 2}K1t>10", solver.ast().unwrap().to_string());
+        let dist = Solver::new("{1d6, 1d6}K1").unwrap().distribution().unwrap();
+        assert_close(11.0 / 36.0, dist.probability(6));
+        let dist = Solver::new("{1d20, 10}>10")
+            .unwrap()
+            .distribution()
+            .unwrap();
+        assert_close(0.5, dist.probability(1));
+    }
+
+    #[test]
+    fn roll_status_test() {
+        let single = roll("4d6K3", vec![3, 1, 6, 4]);
+        assert_eq!(13, single.get_total());
+        assert_eq!("[3, ~~1~~, 6, 4]", single.to_string_history());
+        let single = roll("3d6r1", vec![1, 5, 2, 4]);
+        assert_eq!(11, single.get_total());
+        assert_eq!("[~~1~~↻, 4, 5, 2]", single.to_string_history());
+        let single = roll("2d6!", vec![6, 2, 6, 3]);
+        assert_eq!(17, single.get_total());
+        assert_eq!("[6!, 2, 6!, 3]", single.to_string_history());
+        let single = roll("2d6!", vec![6, 2, 4]);
+        assert_eq!(12, single.get_total());
+        assert_eq!("[6!, 2, 4]", single.to_string_history());
+        let single = roll("4d10t8f1", vec![9, 1, 4, 10]);
+        assert_eq!(1, single.get_total());
+        assert_eq!("[9✓, 1✗, 4, 10✓]", single.to_string_history());
+        let single = roll("3d6!!d1", vec![6, 2, 1, 4]);
+        assert_eq!(12, single.get_total());
+        assert_eq!("[6+4, 2, ~~1~~]", single.to_string_history());
+        let mut single = roll("4d6", vec![3, 1, 6, 4]);
+        single.sort_history(true);
+        assert_eq!("[6, 4, 3, 1]", single.to_string_history());
+        let History::Roll(pool) = &single.get_history()[0] else {
+            unreachable!()
+        };
+        assert!(pool.iter().all(crate::dice::Result::is_counted));
+    }
+
+    #[test]
+    fn modifier_pipeline_test() {
+        let single = roll("4d6!K3", vec![6, 2, 3, 1, 5]);
+        assert_eq!(14, single.get_total());
+        assert_eq!("[6!, ~~2~~, 3, ~~1~~, 5]", single.to_string_history());
+        let single = roll("10d10r1t7", vec![1, 7, 8, 2, 3, 10, 1, 5, 6, 9, 7, 1]);
+        assert_eq!(5, single.get_total());
+        assert_eq!(
+            "[~~1~~↻, 7✓, 7✓, 8✓, 2, 3, 10✓, ~~1~~↻, 1, 5, 6, 9✓]",
+            single.to_string_history()
+        );
+        let single = roll("4d6K3r1", vec![1, 1, 4, 5, 6]);
+        assert_eq!(15, single.get_total());
+        assert_eq!("[~~1~~, ~~1~~↻, 6, 4, 5]", single.to_string_history());
+        let single = roll("3d6d1!", vec![6, 1, 3, 4]);
+        assert_eq!(13, single.get_total());
+        assert_eq!("[6!, ~~1~~, 3, 4]", single.to_string_history());
+        let single = roll("3d6!pK1", vec![6, 6, 2, 3, 1]);
+        assert_eq!(8, single.get_total());
+        assert_eq!("[6+3-1, ~~6+1-1~~, ~~2~~]", single.to_string_history());
+        let single = roll("3d6ir1", vec![1, 1, 2, 1, 5, 4]);
+        assert_eq!(11, single.get_total());
+        assert_eq!(
+            "[~~1~~↻, ~~1~~↻, 5, ~~1~~↻, 4, 2]",
+            single.to_string_history()
+        );
+        let single = roll("2d6e6r1", vec![6, 3, 1, 4]);
+        assert_eq!(13, single.get_total());
+        assert_eq!("[6!, 3, ~~1~~↻, 4]", single.to_string_history());
+        assert_eq!(2, roll("4d10K2t7", vec![9, 8, 2, 1]).get_total());
+        assert_eq!(0, roll("4d10k2t7", vec![9, 8, 2, 1]).get_total());
+        assert_eq!(3, roll("5d10d1t8f1", vec![9, 1, 8, 3, 10]).get_total());
+        assert_eq!(2, roll("5d10t8f1", vec![9, 1, 8, 3, 10]).get_total());
+        let single = roll("4d6r1!K3", vec![1, 6, 2, 3, 4, 5]);
+        assert_eq!(15, single.get_total());
+        assert_eq!(
+            "[~~1~~↻, 4, 6!, ~~2~~, ~~3~~, 5]",
+            single.to_string_history()
+        );
+        let single = roll("4d6!r<=2K3", vec![6, 1, 3, 3, 2, 4, 5]);
+        assert_eq!(15, single.get_total());
+        assert_eq!(
+            "[6!, ~~1~~↻, 4, ~~3~~, ~~3~~, ~~2~~↻, 5]",
+            single.to_string_history()
+        );
+        let single = roll("3d6!!K2", vec![6, 6, 2, 1, 4]);
+        assert_eq!(17, single.get_total());
+        assert_eq!("[6+1, 6+4, ~~2~~]", single.to_string_history());
+        let single = roll("3d6!!k1", vec![6, 3, 2, 1]);
+        assert_eq!(2, single.get_total());
+        assert_eq!("[~~6+1~~, ~~3~~, 2]", single.to_string_history());
+        let single = roll("3d6!pt6", vec![6, 6, 2, 3, 5]);
+        assert_eq!(2, single.get_total());
+        assert_eq!("[6✓+3-1, 6✓+5-1, 2]", single.to_string_history());
+        assert_eq!(1, roll("3d10!!t15", vec![10, 7, 8, 9]).get_total());
+        let single = roll("4d6!t5f1", vec![6, 1, 5, 2, 3]);
+        assert_eq!(1, single.get_total());
+        assert_eq!("[6!✓, 1✗, 5✓, 2, 3]", single.to_string_history());
+        let single = roll("4d6r1K2t5", vec![1, 5, 2, 6, 3]);
+        assert_eq!(2, single.get_total());
+        assert_eq!("[~~1~~↻, ~~3~~, 5✓, ~~2~~, 6✓]", single.to_string_history());
+        assert!(matches!(
+            Solver::new("2d6d1K2").unwrap().solve(),
+            Err(Error::NotEnoughDiceToKeep { .. })
+        ));
+        let dist = Solver::new("1d6r1t4").unwrap().distribution().unwrap();
+        assert_close(7.0 / 12.0, dist.mean());
+        let error = Solver::new("4d6K3r1").unwrap().distribution().unwrap_err();
+        assert_eq!("E0201", error.code());
+    }
+
+    #[test]
+    fn dynamic_dice_test() {
+        let single = roll("(1d4)d6", vec![3, 4, 2, 5]);
+        assert_eq!(11, single.get_total());
+        assert_eq!("`([3])d6 → [4, 2, 5]` = **11**", single.to_string());
+        let single = roll("1d(2d6)", vec![3, 4, 6]);
+        assert_eq!(6, single.get_total());
+        assert_eq!("1d([3, 4]) → [6]", single.to_string_history());
+        let single = roll("(1d2)d(1d6)K1 + 1", vec![2, 4, 1, 3]);
+        assert_eq!(4, single.get_total());
+        assert_eq!("([2])d([4])K1 → [~~1~~, 3] + 1", single.to_string_history());
+        let single = roll("(1d4 - 1)d6", vec![1]);
+        assert_eq!(0, single.get_total());
+        assert_eq!("([1] - 1)d6 → []", single.to_string_history());
+        let stats = HashMap::from([("level".to_owned(), 3)]);
+        let result = Solver::new("(2 + @level)d8")
+            .unwrap()
+            .solve_with_resolver(
+                &mut Mock {
+                    generator: &mut vec![1, 2, 3, 4, 5].into_iter(),
+                },
+                &stats,
+            )
+            .unwrap();
+        assert_eq!(15, result.as_single().unwrap().get_total());
+        let error = Solver::new("(1d4 - 3)d6")
+            .unwrap()
+            .solve_with_source(&mut Mock {
+                generator: &mut vec![1].into_iter(),
+            })
+            .unwrap_err();
+        assert_eq!(
+            Error::InvalidCount {
+                span: ast::Span::new(1, 8),
+                value: -2
+            },
+            error
+        );
+        assert_eq!("E0110", error.code());
+        assert!(matches!(
+            Solver::new("1d(3 - 3)").unwrap().solve(),
+            Err(Error::InvalidCount { value: 0, .. })
+        ));
+        assert!(matches!(
+            Solver::new("(10000)d6").unwrap().solve(),
+            Err(Error::TooManyDice { .. })
+        ));
+        assert!(matches!(
+            Solver::new("1d(6000)").unwrap().solve(),
+            Err(Error::TooManySides { .. })
+        ));
+        let solver = Solver::new("(5001)d6").unwrap();
+        assert_eq!(
+            solver.solve().unwrap_err(),
+            solver.distribution().unwrap_err()
+        );
+        let solver = Solver::new("1d(6000)").unwrap();
+        assert_eq!(
+            solver.solve().unwrap_err(),
+            solver.distribution().unwrap_err()
+        );
+        let solver = Solver::new("(1d4)d6 + 1d(2d6)K1 + ((2))")
+            .unwrap()
+            .compile()
+            .unwrap();
+        assert_eq!(
+            "(1d4)d6 + 1d(2d6)K1 + ((2))",
+            solver.ast().unwrap().to_string()
+        );
+        let dist = Solver::new("(1d2)d6").unwrap().distribution().unwrap();
+        assert_close(5.25, dist.mean());
+        let dist = Solver::new("1d(1d2 * 2)").unwrap().distribution().unwrap();
+        assert_close(2.0, dist.mean());
+        assert!(Solver::new("(1d4 - 2)d6").unwrap().distribution().is_err());
+        for input in ["(1d100)d(1d100)", "1d(1d5000)", "(1d4)d(1d2000)"] {
+            let start = std::time::Instant::now();
+            let error = Solver::new(input).unwrap().distribution().unwrap_err();
+            assert_eq!(
+                Error::TooComplex {
+                    span: ast::Span::new(0, input.len())
+                },
+                error
+            );
+            assert!(start.elapsed() < std::time::Duration::from_secs(1));
+        }
+        let dist = Solver::new("(1d20)d(1d20)")
+            .unwrap()
+            .distribution()
+            .unwrap();
+        assert_eq!(400, dist.max());
+    }
+
+    #[test]
+    fn selector_test() {
+        let single = roll("5d6km3", vec![1, 6, 3, 4, 2]);
+        assert_eq!(9, single.get_total());
+        assert_eq!("[~~1~~, ~~6~~, 3, 4, 2]", single.to_string_history());
+        assert_eq!(5, roll("4d6km2", vec![1, 2, 3, 4]).get_total());
+        assert_eq!(2, roll("4d6km1", vec![1, 2, 3, 4]).get_total());
+        let single = roll("4d6k>3", vec![1, 4, 6, 3]);
+        assert_eq!(10, single.get_total());
+        assert_eq!("[~~1~~, 4, 6, ~~3~~]", single.to_string_history());
+        assert_eq!(7, roll("4d6d<2", vec![1, 4, 1, 3]).get_total());
+        let single = roll("5d6u", vec![3, 3, 5, 1, 5]);
+        assert_eq!(9, single.get_total());
+        assert_eq!("[3, ~~3~~, 5, 1, ~~5~~]", single.to_string_history());
+        assert_eq!(15, roll("6d6k>2K3", vec![1, 2, 6, 5, 4, 3]).get_total());
+        assert_eq!(
+            "[1, 3, 4, 6]",
+            roll("4d6s", vec![3, 1, 6, 4]).to_string_history()
+        );
+        assert_eq!(
+            "[6, 4, 3, 1]",
+            roll("4d6sd", vec![3, 1, 6, 4]).to_string_history()
+        );
+        let single = roll("4d6sd1", vec![3, 1, 6, 4]);
+        assert_eq!(13, single.get_total());
+        assert_eq!("[~~1~~, 3, 4, 6]", single.to_string_history());
+        let single = roll("4d6u t4 sd", vec![4, 4, 5, 1]);
+        assert_eq!(2, single.get_total());
+        assert_eq!("[5✓, 4✓, ~~4~~, 1]", single.to_string_history());
+        assert_eq!(7, roll("{1d6, 1d6, 1d6}u", vec![2, 2, 5]).get_total());
+        assert_eq!(4, roll("{1d6, 1d6, 1d6}km1", vec![2, 5, 4]).get_total());
+        assert_eq!(5, roll("{1d6, 1d6}k>3", vec![2, 5]).get_total());
+        assert!(matches!(
+            Solver::new("2d6km3").unwrap().solve(),
+            Err(Error::NotEnoughDiceToKeep { .. })
+        ));
+        let solver = Solver::new("5d6km3k>2d<=1uK2sd + {1d6, 1d6}u")
+            .unwrap()
+            .compile()
+            .unwrap();
+        assert_eq!(
+            "5d6km3k>2d<=1uK2sd + {1d6, 1d6}u",
+            solver.ast().unwrap().to_string()
+        );
+        let dist = Solver::new("4d6k>3").unwrap().distribution().unwrap();
+        assert_close(10.0, dist.mean());
+        let dist = Solver::new("2d6d<4s").unwrap().distribution().unwrap();
+        assert_close(1.0 / 4.0, dist.probability(0));
+        let error = Solver::new("4d6km2").unwrap().distribution().unwrap_err();
+        assert_eq!("E0201", error.code());
+    }
+
+    #[test]
+    fn set_match_test() {
+        let single = roll("7d10m", vec![8, 8, 4, 8, 4, 1, 2]);
+        assert_eq!(35, single.get_total());
+        assert_eq!(
+            "[8, 8, 4, 8, 4, 1, 2] → 3x8, 2x4",
+            single.to_string_history()
+        );
+        assert_eq!(
+            vec![
+                crate::dice::Set {
+                    width: 3,
+                    height: 8
+                },
+                crate::dice::Set {
+                    width: 2,
+                    height: 4
+                }
+            ],
+            single.get_sets()
+        );
+        let single = roll("7d10mt", vec![8, 8, 4, 8, 4, 1, 2]);
+        assert_eq!(2, single.get_total());
+        assert_eq!(
+            "`[8, 8, 4, 8, 4, 1, 2] → 3x8, 2x4` = **2**",
+            single.to_string()
+        );
+        assert_eq!(1, roll("5d10mt3", vec![8, 8, 4, 8, 4]).get_total());
+        assert_eq!(1, roll("6d10d1mt", vec![1, 1, 5, 5, 7, 2]).get_total());
+        let single = roll("3d10m + 1", vec![1, 2, 3]);
+        assert_eq!(7, single.get_total());
+        assert_eq!("[1, 2, 3] → no set + 1", single.to_string_history());
+        assert!(single.get_sets().is_empty());
+        let result = solve("2 * 4d6m + 3d6m", vec![3, 3, 1, 2, 5, 5, 5]);
+        let sets = result.get_sets();
+        assert_eq!(2, sets.len());
+        assert_eq!("2x3", sets[0].to_string());
+        assert_eq!("3x5", sets[1].to_string());
+        let solver = Solver::new("7d10mt + 5d10m3 + 4d6m")
+            .unwrap()
+            .compile()
+            .unwrap();
+        assert_eq!("7d10mt + 5d10m3 + 4d6m", solver.ast().unwrap().to_string());
+        let dist = Solver::new("3d6m").unwrap().distribution().unwrap();
+        assert_close(10.5, dist.mean());
+        let error = Solver::new("3d6mt").unwrap().distribution().unwrap_err();
+        assert_eq!("E0201", error.code());
+    }
+
+    #[test]
+    fn wild_die_test() {
+        let single = roll("d8w", vec![5, 3]);
+        assert_eq!(5, single.get_total());
+        assert_eq!("[5, ~~3ʷ~~]", single.to_string_history());
+        assert_eq!(12, roll("d8w", vec![8, 2, 4]).get_total());
+        let single = roll("d4w", vec![2, 6, 6, 3]);
+        assert_eq!(15, single.get_total());
+        assert_eq!("[~~2~~, 6ʷ+6+3]", single.to_string_history());
+        let single = roll("d8w t4", vec![8, 1, 5]);
+        assert_eq!(3, single.get_total());
+        assert_eq!("[8✓+5, ~~1ʷ~~]", single.to_string_history());
+        assert_eq!(0, roll("d8wt4", vec![3, 2]).get_total());
+        assert_eq!(10, roll("2d8w", vec![3, 7, 2]).get_total());
+        let single = roll("d8w", vec![1, 1]);
+        assert_eq!(1, single.get_total());
+        assert_eq!("[~~1~~, 1ʷ] → critical failure", single.to_string_history());
+        let History::Roll(pool) = &single.get_history()[0] else {
+            unreachable!()
+        };
+        assert!(pool.iter().all(|r| r.critic == Critic::Fumble));
+        let single = roll("d8w", vec![1, 2]);
+        assert_eq!("[~~1~~, 2ʷ]", single.to_string_history());
+        let solver = Solver::new("d8w t4 + 1d6w").unwrap().compile().unwrap();
+        assert_eq!("1d8wt4 + 1d6w", solver.ast().unwrap().to_string());
+        let error = Solver::new("d8w").unwrap().distribution().unwrap_err();
+        assert_eq!("E0201", error.code());
+        for input in ["dFw", "1dF.1w", "d{1,2}w", "2d{0..9}w"] {
+            let error = Solver::new(input).unwrap().compile().unwrap_err();
+            assert_eq!("E0001", error.code(), "{input}");
+        }
+        assert_eq!(5, roll("1d(1d4)w", vec![2, 1, 5]).get_total());
+    }
+
+    #[test]
+    fn shadowrun_test() {
+        let result = solve("6d6sr", vec![1, 1, 1, 1, 5, 6]);
+        let single = result.as_single().unwrap();
+        assert_eq!(2, single.get_total());
+        assert_eq!("[1, 1, 1, 1, 5✓, 6✓] → glitch", single.to_string_history());
+        assert_eq!(Some(Glitch::Glitch), result.get_glitch());
+        let result = solve("4d6sr", vec![1, 1, 1, 2]);
+        assert_eq!(0, result.as_single().unwrap().get_total());
+        assert_eq!(Some(Glitch::Critical), result.get_glitch());
+        let result = solve("4d6sr", vec![1, 1, 5, 3]);
+        assert_eq!(1, result.as_single().unwrap().get_total());
+        assert_eq!(None, result.get_glitch());
+        let result = solve("3d6sr!", vec![6, 1, 3, 6, 2]);
+        let single = result.as_single().unwrap();
+        assert_eq!(2, single.get_total());
+        assert_eq!("[6!✓, 1, 3, 6!✓, 2]", single.to_string_history());
+        assert_eq!(None, result.get_glitch());
+        let result = solve("4d6sr!", vec![6, 1, 1, 1, 6, 6, 2]);
+        let single = result.as_single().unwrap();
+        assert_eq!(3, single.get_total());
+        assert_eq!(
+            "[6!✓, 1, 1, 1, 6!✓, 6!✓, 2] → glitch",
+            single.to_string_history()
+        );
+        assert_eq!(Some(Glitch::Glitch), result.get_glitch());
+        let result = solve("4d6sr!", vec![6, 1, 1, 3, 6, 1]);
+        assert_eq!(2, result.as_single().unwrap().get_total());
+        assert_eq!(Some(Glitch::Glitch), result.get_glitch());
+        let result = solve("let a = 2d6sr; a + 1", vec![1, 1]);
+        assert_eq!(Some(Glitch::Critical), result.get_glitch());
+        assert_eq!(1, result.as_single().unwrap().get_total());
+        let result = solve("2d6sr1", vec![1, 4, 2]);
+        assert_eq!(6, result.as_single().unwrap().get_total());
+        let solver = Solver::new("12d6sr! + 12d6 sr").unwrap().compile().unwrap();
+        assert_eq!("12d6sr! + 12d6sr", solver.ast().unwrap().to_string());
+        let dist = Solver::new("2d6sr").unwrap().distribution().unwrap();
+        assert_close(1.0 / 9.0, dist.probability(2));
+    }
+
+    /// Same expression without spans nor parentheses, to compare the shape of two expressions
+    fn unspanned(expr: &ast::Expr) -> ast::Expr {
+        let inner = |expr: &ast::Expr| Box::new(unspanned(expr));
+        let kind = match &expr.kind {
+            ast::ExprKind::Block(expr) => return unspanned(expr),
+            ast::ExprKind::Neg(expr) => ast::ExprKind::Neg(inner(expr)),
+            ast::ExprKind::Call(function, args) => {
+                ast::ExprKind::Call(*function, args.iter().map(unspanned).collect())
+            }
+            ast::ExprKind::Binary(lhs, op, rhs) => {
+                ast::ExprKind::Binary(inner(lhs), *op, inner(rhs))
+            }
+            ast::ExprKind::If(cond, then, other) => {
+                ast::ExprKind::If(inner(cond), inner(then), inner(other))
+            }
+            ast::ExprKind::DynamicDice(amount, sides, dice) => ast::ExprKind::DynamicDice(
+                amount.as_deref().map(inner),
+                sides.as_deref().map(inner),
+                dice.clone(),
+            ),
+            ast::ExprKind::Group(exprs, modifiers) => {
+                ast::ExprKind::Group(exprs.iter().map(unspanned).collect(), modifiers.clone())
+            }
+            kind => kind.clone(),
+        };
+        ast::Expr::new(kind, ast::Span::default())
+    }
+
+    #[test]
+    fn display_roundtrip_test() {
+        let leaf = |kind| Box::new(ast::Expr::new(kind, ast::Span::default()));
+        let binary = |lhs, op, rhs| ast::ExprKind::Binary(leaf(lhs), op, leaf(rhs));
+        let (one, two) = (ast::ExprKind::Integer(1), ast::ExprKind::Integer(2));
+        let sum = binary(one.clone(), ast::Operator::Add, two.clone());
+        let diff = binary(one.clone(), ast::Operator::Sub, two.clone());
+        let pow = binary(one.clone(), ast::Operator::Pow, two.clone());
+        let exprs = vec![
+            (
+                binary(sum.clone(), ast::Operator::Mul, two.clone()),
+                "(1 + 2) * 2",
+            ),
+            (
+                binary(one.clone(), ast::Operator::Sub, diff.clone()),
+                "1 - (1 - 2)",
+            ),
+            (
+                binary(diff.clone(), ast::Operator::Sub, one.clone()),
+                "1 - 2 - 1",
+            ),
+            (
+                binary(pow.clone(), ast::Operator::Pow, two.clone()),
+                "(1 ** 2) ** 2",
+            ),
+            (
+                binary(two.clone(), ast::Operator::Pow, pow.clone()),
+                "2 ** 1 ** 2",
+            ),
+            (ast::ExprKind::Neg(leaf(sum.clone())), "-(1 + 2)"),
+            (ast::ExprKind::Neg(leaf(pow.clone())), "-1 ** 2"),
+            (
+                ast::ExprKind::Neg(leaf(ast::ExprKind::Integer(-2))),
+                "-(-2)",
+            ),
+            (
+                binary(ast::ExprKind::Integer(-1), ast::Operator::Pow, two.clone()),
+                "(-1) ** 2",
+            ),
+            (binary(sum, ast::Operator::Ge, one), "1 + 2 >= 1"),
+            (ast::ExprKind::Float(1e20), "100000000000000000000.0"),
+            (ast::ExprKind::Float(2.25), "2.25"),
+        ];
+        for (kind, text) in exprs {
+            let expr = ast::Expr::new(kind, ast::Span::default());
+            assert_eq!(text, expr.to_string());
+            let command = Solver::new(text).unwrap().compile().unwrap();
+            match &command.ast().unwrap().root {
+                ast::Root::Expr(parsed) => assert_eq!(expr, unspanned(parsed)),
+                _ => unreachable!(),
+            }
+        }
+        let neg = ast::ExprKind::Neg(leaf(ast::ExprKind::Neg(leaf(two.clone()))));
+        assert_eq!(
+            "-(-2)",
+            ast::Expr::new(neg, ast::Span::default()).to_string()
+        );
+        let input = "max(1d6, (2d4)d(1d6)) * -(1.5 + {1d6, 2}K1) ** 2 < 3 - @dex";
+        let command = Solver::new(input).unwrap().compile().unwrap();
+        let ast = command.ast().unwrap();
+        let reparsed = Solver::from_ast(ast.clone()).compile().unwrap();
+        match (&ast.root, &reparsed.ast().unwrap().root) {
+            (ast::Root::Expr(expr), ast::Root::Expr(parsed)) => {
+                assert_eq!(unspanned(expr), unspanned(parsed))
+            }
+            _ => unreachable!(),
+        }
+    }
+
     #[test]
     fn sandbox_test() {
         let solver = Solver::new("5d6 + 4 * 2").unwrap();
@@ -0,0 +1,41 @@
+/// Arbitrary limits to avoid oom or endless loops
+const MAX_DICE_AMOUNT: u64 = 5000;
+const MAX_DICE_SIDES: u64 = 5000;
+const MAX_DICE_THROWN: u64 = 100_000;
+const MAX_DEPTH: u64 = 1000;
+const MAX_REPEAT: u64 = 100;
+const MAX_HISTORY: u64 = 100_000;
+const MAX_SIMULATED_THROWN: u64 = 10_000_000;
+
+/// Caps on the work done to solve a single query
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EvalLimits {
+    /// Dices in a single dice term
+    pub max_dice_amount: u64,
+    /// Sides of a single dice term
+    pub max_dice_sides: u64,
+    /// Dices thrown over the whole query, explosions and rerolls included
+    pub max_dice_thrown: u64,
+    /// Successive explosions of a pool, or rerolls of a single dice
+    pub max_depth: u64,
+    /// Times an expression can be repeated with `^`
+    pub max_repeat: u64,
+    /// Dice results kept in the histories shown to the user
+    pub max_history: u64,
+    /// Dices thrown over all the samples of a simulation
+    pub max_simulated_thrown: u64,
+}
+
+impl Default for EvalLimits {
+    fn default() -> Self {
+        Self {
+            max_dice_amount: MAX_DICE_AMOUNT,
+            max_dice_sides: MAX_DICE_SIDES,
+            max_dice_thrown: MAX_DICE_THROWN,
+            max_depth: MAX_DEPTH,
+            max_repeat: MAX_REPEAT,
+            max_history: MAX_HISTORY,
+            max_simulated_thrown: MAX_SIMULATED_THROWN,
+        }
+    }
+}
@@ -1,5 +1,10 @@
+use crate::ast;
+use crate::climber::C;
+use crate::error::Error;
+use crate::error::Result;
 use pest::iterators::Pair;
 use pest::iterators::Pairs;
+use pest::Parser as _;
 use pest_derive::Parser;
 
 /// Pest parser
@@ -11,7 +16,15 @@ impl Parser {
     pub fn extract_dice(expr: &mut Pairs<Rule>) -> Option<String> {
         for inner in expr.by_ref() {
             match inner.as_rule() {
-                Rule::expr | Rule::block_expr => {
+                Rule::block_expr
+                    if inner
+                        .clone()
+                        .into_inner()
+                        .any(|p| p.as_rule() == Rule::roll) =>
+                {
+                    return Some(inner.as_str().trim().to_owned())
+                }
+                Rule::expr | Rule::block_expr | Rule::binding | Rule::group => {
                     return Self::extract_dice(&mut inner.into_inner())
                 }
                 Rule::dice => return Some(inner.as_str().trim().to_owned()),
@@ -21,10 +34,355 @@ impl Parser {
         None
     }
 
-    pub fn extract_modifier_value(modifier: Pair<Rule>) -> Option<u64> {
+    pub fn extract_modifier_value(modifier: Pair<Rule>) -> Result<Option<u64>> {
+        modifier
+            .into_inner()
+            .next()
+            .map(|value| Self::parse_number(&value))
+            .transpose()
+    }
+
+    /// Comparison point of a modifier, `op` when there's only a number
+    fn extract_point(modifier: Pair<Rule>, op: ast::CompareOp) -> Result<Option<ast::Compare>> {
         modifier
             .into_inner()
             .next()
-            .map(|value| value.as_str().parse::<u64>().unwrap())
+            .map(|point| {
+                let mut pairs = point.into_inner();
+                let mut pair = pairs.next().unwrap();
+                let op = match pair.as_rule() {
+                    Rule::point_op => {
+                        let op = match pair.as_str() {
+                            "=" => ast::CompareOp::Eq,
+                            "<" => ast::CompareOp::Lt,
+                            "<=" => ast::CompareOp::Le,
+                            ">" => ast::CompareOp::Gt,
+                            ">=" => ast::CompareOp::Ge,
+                            _ => unreachable!("{:#?}", pair),
+                        };
+                        pair = pairs.next().unwrap();
+                        op
+                    }
+                    _ => op,
+                };
+                Ok(ast::Compare::new(op, Self::parse_number(&pair)?))
+            })
+            .transpose()
+    }
+
+    /// Parse a numeric token, the grammar only lets through values too large to fit
+    fn parse_number<T: std::str::FromStr>(pair: &Pair<Rule>) -> Result<T> {
+        pair.as_str()
+            .replace(' ', "")
+            .parse::<T>()
+            .map_err(|_| Error::NumberTooLarge {
+                span: Self::span_of(pair),
+            })
+    }
+
+    /// Span of a pair, without the whitespace some rules swallow at their end
+    fn span_of(pair: &Pair<Rule>) -> ast::Span {
+        let start = pair.as_span().start();
+        ast::Span::new(start, start + pair.as_str().trim_end().len())
+    }
+
+    /// Parse a whole query into its typed expression
+    pub(crate) fn parse_command(input: &str) -> Result<ast::Command> {
+        let mut bindings: Vec<ast::Binding> = Vec::new();
+        let mut exprs = Vec::new();
+        let mut root = None;
+        let mut reason = None;
+        for pair in Self::parse(Rule::command, input)? {
+            let names = bindings.iter().map(|b| b.name.as_str()).collect::<Vec<_>>();
+            match pair.as_rule() {
+                Rule::binding => {
+                    let span = Self::span_of(&pair);
+                    let mut pairs = pair.into_inner();
+                    let ident = pairs.next().unwrap();
+                    let name = ident.as_str().to_owned();
+                    if Self::parse(Rule::name, &name).is_err() {
+                        let span = Self::span_of(&ident);
+                        return Err(Error::DiceName { span, name });
+                    }
+                    let expr = Self::build_expr(pairs.next().unwrap().into_inner(), &names)?;
+                    bindings.push(ast::Binding { name, expr, span });
+                }
+                Rule::expr => exprs.push(Self::build_expr(pair.into_inner(), &names)?),
+                Rule::repeated_expr => {
+                    root = Some(ast::Root::Repeat(Self::build_repeat(pair, &names)?))
+                }
+                Rule::reason => reason = Some(pair.as_str()[1..].trim().to_owned()),
+                Rule::EOI => (),
+                _ => unreachable!("{:#?}", pair),
+            }
+        }
+        let root = match root {
+            Some(root) => root,
+            None if exprs.len() == 1 => ast::Root::Expr(exprs.pop().unwrap()),
+            None => ast::Root::List(exprs),
+        };
+        Ok(ast::Command {
+            bindings,
+            root,
+            reason,
+        })
+    }
+
+    fn build_repeat(pair: Pair<Rule>, names: &[&str]) -> Result<ast::Repeat> {
+        let span = Self::span_of(&pair);
+        let mut pairs = pair.into_inner();
+        let expr = Self::build_expr(pairs.next().unwrap().into_inner(), names)?;
+        let repeat = pairs.next().unwrap();
+        let (times, mode) = match repeat.as_rule() {
+            Rule::nb_dice => (repeat, ast::RepeatMode::List),
+            Rule::add => (pairs.next().unwrap(), ast::RepeatMode::Sum),
+            Rule::sort => (pairs.next().unwrap(), ast::RepeatMode::Sort),
+            _ => unreachable!("{:#?}", repeat),
+        };
+        Ok(ast::Repeat {
+            expr,
+            times: Self::parse_number(&times)?,
+            mode,
+            span,
+        })
+    }
+
+    /// Build an expression, only the `names` bound before it can be used
+    fn build_expr(expr: Pairs<Rule>, names: &[&str]) -> Result<ast::Expr> {
+        C.raise(
+            expr,
+            |pair: Pair<Rule>| {
+                let span = Self::span_of(&pair);
+                let kind = match pair.as_rule() {
+                    Rule::integer => ast::ExprKind::Integer(Self::parse_number(&pair)?),
+                    Rule::float => match Self::parse_number::<f64>(&pair)? {
+                        n if n.is_finite() => ast::ExprKind::Float(n),
+                        _ => return Err(Error::NumberTooLarge { span }),
+                    },
+                    Rule::block_expr if pair.clone().into_inner().nth(1).is_some() => {
+                        Self::build_dice(pair.into_inner(), names)?
+                    }
+                    Rule::block_expr => ast::ExprKind::Block(Box::new(Self::build_expr(
+                        pair.into_inner().next().unwrap().into_inner(),
+                        names,
+                    )?)),
+                    Rule::dice => Self::build_dice(pair.into_inner(), names)?,
+                    Rule::function => Self::build_call(pair, names)?,
+                    Rule::group => {
+                        let mut exprs = Vec::new();
+                        let mut modifiers = Vec::new();
+                        for inner in pair.into_inner() {
+                            match inner.as_rule() {
+                                Rule::expr => {
+                                    exprs.push(Self::build_expr(inner.into_inner(), names)?)
+                                }
+                                _ => modifiers.push(Self::build_modifier(inner)?),
+                            }
+                        }
+                        ast::ExprKind::Group(exprs, modifiers)
+                    }
+                    Rule::conditional => {
+                        let mut branches = pair.into_inner().map(|branch| {
+                            Self::build_expr(branch.into_inner(), names).map(Box::new)
+                        });
+                        let cond = branches.next().unwrap()?;
+                        let then = branches.next().unwrap()?;
+                        let other = branches.next().unwrap()?;
+                        ast::ExprKind::If(cond, then, other)
+                    }
+                    Rule::name if names.contains(&pair.as_str()) => {
+                        ast::ExprKind::Variable(pair.as_str().to_owned())
+                    }
+                    Rule::external => ast::ExprKind::External(pair.as_str()[1..].to_owned()),
+                    Rule::name => {
+                        return Err(Error::UnknownName {
+                            span,
+                            name: pair.as_str().to_owned(),
+                        })
+                    }
+                    _ => unreachable!("{:#?}", pair),
+                };
+                Ok(ast::Expr::new(kind, span))
+            },
+            |op: Pair<Rule>, rhs: Result<ast::Expr>| {
+                let rhs = rhs?;
+                let span = Self::span_of(&op).join(&rhs.span);
+                Ok(ast::Expr::new(ast::ExprKind::Neg(Box::new(rhs)), span))
+            },
+            |lhs: Result<ast::Expr>, op: Pair<Rule>, rhs: Result<ast::Expr>| {
+                let operator = match op.as_rule() {
+                    Rule::add => ast::Operator::Add,
+                    Rule::sub => ast::Operator::Sub,
+                    Rule::mul => ast::Operator::Mul,
+                    Rule::div => ast::Operator::Div,
+                    Rule::rem => ast::Operator::Rem,
+                    Rule::pow => ast::Operator::Pow,
+                    Rule::lt => ast::Operator::Lt,
+                    Rule::le => ast::Operator::Le,
+                    Rule::gt => ast::Operator::Gt,
+                    Rule::ge => ast::Operator::Ge,
+                    Rule::eq => ast::Operator::Eq,
+                    Rule::ne => ast::Operator::Ne,
+                    _ => unreachable!("{:#?}", op),
+                };
+                let (lhs, rhs) = (lhs?, rhs?);
+                let span = lhs.span.join(&rhs.span);
+                Ok(ast::Expr::new(
+                    ast::ExprKind::Binary(Box::new(lhs), operator, Box::new(rhs)),
+                    span,
+                ))
+            },
+        )
+    }
+
+    fn build_call(pair: Pair<Rule>, names: &[&str]) -> Result<ast::ExprKind> {
+        let span = Self::span_of(&pair);
+        let mut pairs = pair.into_inner();
+        let name = pairs.next().unwrap();
+        let function = match name.as_str() {
+            "min" => ast::Function::Min,
+            "max" => ast::Function::Max,
+            "abs" => ast::Function::Abs,
+            "floor" => ast::Function::Floor,
+            "ceil" => ast::Function::Ceil,
+            "round" => ast::Function::Round,
+            "clamp" => ast::Function::Clamp,
+            "coc" => ast::Function::Coc,
+            _ => unreachable!("{:#?}", name),
+        };
+        let args = pairs
+            .map(|arg| Self::build_expr(arg.into_inner(), names))
+            .collect::<Result<Vec<_>>>()?;
+        if !function.accepts(args.len()) {
+            return Err(Error::Arity { span, function });
+        }
+        Ok(ast::ExprKind::Call(function, args))
+    }
+
+    /// Build a dice term, its amount and sides can be sub-expressions like in `(1d4)d(2d6)`
+    fn build_dice(mut dice: Pairs<Rule>, names: &[&str]) -> Result<ast::ExprKind> {
+        let maybe_amount = dice.next().unwrap();
+        let (amount, dynamic_amount) = match maybe_amount.as_rule() {
+            Rule::nb_dice => {
+                dice.next(); // skip `d` token
+                (Self::parse_number(&maybe_amount)?, None)
+            }
+            Rule::expr => {
+                dice.next(); // skip `d` token
+                let expr = Self::build_expr(maybe_amount.into_inner(), names)?;
+                (1, Some(Box::new(expr)))
+            }
+            Rule::roll => (1, None),
+            _ => unreachable!("{:?}", maybe_amount),
+        };
+        let pair = dice.next().unwrap();
+        let mut dynamic_sides = None;
+        let sides = match pair.as_rule() {
+            Rule::nb_dice => ast::Sides::Number(Self::parse_number(&pair)?),
+            Rule::dynamic => {
+                let expr = pair.into_inner().next().unwrap().into_inner();
+                dynamic_sides = Some(Box::new(Self::build_expr(expr, names)?));
+                ast::Sides::Number(1)
+            }
+            Rule::fudge => ast::Sides::Fudge(match pair.into_inner().next() {
+                Some(kind) => Self::parse_number(&kind)?,
+                None => 2,
+            }),
+            Rule::percentile => ast::Sides::Percent(match pair.into_inner().next() {
+                Some(tens) => {
+                    let is_bonus = tens.as_rule() == Rule::bonus;
+                    let count: i64 = Self::parse_number(&tens.into_inner().next().unwrap())?;
+                    if is_bonus {
+                        count
+                    } else {
+                        -count
+                    }
+                }
+                None => 0,
+            }),
+            Rule::custom => ast::Sides::Custom(
+                pair.into_inner()
+                    .map(Self::build_range)
+                    .collect::<Result<_>>()?,
+            ),
+            _ => unreachable!("{:?}", pair),
+        };
+        let modifiers = dice.map(Self::build_modifier).collect::<Result<Vec<_>>>()?;
+        let dice = ast::Dice {
+            amount,
+            sides,
+            modifiers,
+        };
+        Ok(match (dynamic_amount, dynamic_sides) {
+            (None, None) => ast::ExprKind::Dice(dice),
+            (amount, sides) => ast::ExprKind::DynamicDice(amount, sides, dice),
+        })
+    }
+
+    /// Inclusive range like `0..9`, or a single value, bounds can be given in any order
+    fn build_range(range: Pair<Rule>) -> Result<std::ops::RangeInclusive<i64>> {
+        let mut bounds = range.into_inner();
+        let start: i64 = Self::parse_number(&bounds.next().unwrap())?;
+        let end = match bounds.next() {
+            Some(end) => Self::parse_number(&end)?,
+            None => start,
+        };
+        Ok(start.min(end)..=start.max(end))
+    }
+
+    fn build_modifier(pair: Pair<Rule>) -> Result<ast::Modifier> {
+        let (ge, le) = (ast::CompareOp::Ge, ast::CompareOp::Le);
+        let modifier = match pair.as_rule() {
+            Rule::explode => ast::Modifier::Explode(Self::extract_point(pair, ge)?.unwrap()),
+            Rule::i_explode => ast::Modifier::IndefExplode(Self::extract_point(pair, ge)?),
+            Rule::compound => ast::Modifier::Compound(Self::extract_point(pair, ge)?),
+            Rule::penetrate => ast::Modifier::Penetrate(Self::extract_point(pair, ge)?),
+            Rule::reroll => ast::Modifier::Reroll(Self::extract_point(pair, le)?.unwrap()),
+            Rule::i_reroll => ast::Modifier::IndefReroll(Self::extract_point(pair, le)?.unwrap()),
+            Rule::keep_hi => ast::Modifier::KeepHigh(Self::extract_modifier_value(pair)?.unwrap()),
+            Rule::keep_lo => ast::Modifier::KeepLow(Self::extract_modifier_value(pair)?.unwrap()),
+            Rule::drop_hi => ast::Modifier::DropHigh(Self::extract_modifier_value(pair)?.unwrap()),
+            Rule::drop_lo => ast::Modifier::DropLow(Self::extract_modifier_value(pair)?.unwrap()),
+            Rule::keep_mid => {
+                ast::Modifier::KeepMiddle(Self::extract_modifier_value(pair)?.unwrap())
+            }
+            Rule::keep_point => ast::Modifier::KeepIf(Self::extract_point(pair, ge)?.unwrap()),
+            Rule::drop_point => ast::Modifier::DropIf(Self::extract_point(pair, ge)?.unwrap()),
+            Rule::unique => ast::Modifier::Unique,
+            Rule::wild => ast::Modifier::Wild,
+            Rule::shadowrun => ast::Modifier::Shadowrun,
+            Rule::sort_dice => ast::Modifier::Sort(pair.into_inner().next().is_some()),
+            Rule::set_match => {
+                let mut count = false;
+                let mut width = 2;
+                for inner in pair.into_inner() {
+                    match inner.as_rule() {
+                        Rule::count_sets => count = true,
+                        _ => width = Self::parse_number(&inner)?,
+                    }
+                }
+                ast::Modifier::Match(width, count)
+            }
+            Rule::target => {
+                let target = pair.clone().into_inner().next().unwrap();
+                match target.as_rule() {
+                    Rule::point => ast::Modifier::Target(Self::extract_point(pair, ge)?.unwrap()),
+                    Rule::target_enum => ast::Modifier::TargetEnum(
+                        target
+                            .into_inner()
+                            .map(Self::build_range)
+                            .collect::<Result<_>>()?,
+                    ),
+                    _ => unreachable!("{:#?}", target),
+                }
+            }
+            Rule::double_target => {
+                ast::Modifier::DoubleTarget(Self::extract_point(pair, ge)?.unwrap())
+            }
+            Rule::failure => ast::Modifier::Failure(Self::extract_point(pair, le)?.unwrap()),
+            Rule::group_target => ast::Modifier::Target(Self::extract_point(pair, ge)?.unwrap()),
+            _ => unreachable!("{:#?}", pair),
+        };
+        Ok(modifier)
     }
 }
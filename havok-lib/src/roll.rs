@@ -1,6 +1,10 @@
 pub mod history;
 pub mod kind;
 
+use crate::dice;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+
 /// Keep the roll expression type
 #[derive(Debug, Clone)]
 pub enum Kind {
@@ -12,6 +16,8 @@ pub enum Kind {
 #[derive(Debug, Clone)]
 pub struct Result {
     result: Kind,
+    /// Named results of the `let` bindings, in order
+    bindings: Vec<(String, kind::Single)>,
     reason: Option<String>,
 }
 
@@ -20,6 +26,7 @@ impl Result {
     pub fn new_single(single: kind::Single) -> Self {
         Result {
             result: Kind::Single(single),
+            bindings: Vec::new(),
             reason: None,
         }
     }
@@ -28,10 +35,19 @@ impl Result {
     pub fn new_multi(rolls: Vec<kind::Single>, total: Option<i64>) -> Self {
         Result {
             result: Kind::Multi(kind::Multi { rolls, total }),
+            bindings: Vec::new(),
             reason: None,
         }
     }
 
+    pub fn add_bindings(&mut self, bindings: Vec<(String, kind::Single)>) {
+        self.bindings = bindings;
+    }
+
+    pub fn get_bindings(&self) -> &[(String, kind::Single)] {
+        &self.bindings
+    }
+
     pub fn add_reason(&mut self, reason: String) {
         self.reason = Some(reason);
     }
@@ -44,6 +60,31 @@ impl Result {
         &self.result
     }
 
+    /// Worst glitch of the Shadowrun pools rolled, bindings included
+    pub fn get_glitch(&self) -> Option<dice::Glitch> {
+        let rolls = match &self.result {
+            Kind::Single(single) => std::slice::from_ref(single),
+            Kind::Multi(multi) => multi.rolls.as_slice(),
+        };
+        rolls
+            .iter()
+            .chain(self.bindings.iter().map(|(_, single)| single))
+            .filter_map(kind::Single::get_glitch)
+            .max()
+    }
+
+    /// Sets of dice of equal value matched by the rolls, bindings count where they're used
+    pub fn get_sets(&self) -> Vec<dice::Set> {
+        let rolls = match &self.result {
+            Kind::Single(single) => std::slice::from_ref(single),
+            Kind::Multi(multi) => multi.rolls.as_slice(),
+        };
+        rolls
+            .iter()
+            .flat_map(|single| single.get_sets().iter().copied())
+            .collect()
+    }
+
     /// Check and return result as single roll expression
     pub fn as_single(&self) -> Option<&kind::Single> {
         match &self.result {
@@ -63,9 +104,12 @@ impl Result {
 
 impl std::fmt::Display for Result {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (name, single) in &self.bindings {
+            writeln!(f, "*{}* {}", name, single)?;
+        }
         match &self.result {
             Kind::Single(single) => {
-                write!(f, "{}", single.to_string())?;
+                write!(f, "{}", single)?;
                 if let Some(reason) = &self.reason {
                     write!(f, " *reason* `{}`", reason)?;
                 }
@@ -83,7 +127,7 @@ impl std::fmt::Display for Result {
                 None => {
                     (*multi)
                         .iter()
-                        .try_for_each(|result| writeln!(f, "{}", result.to_string()))?;
+                        .try_for_each(|result| writeln!(f, "{}", result))?;
                     if let Some(reason) = &self.reason {
                         write!(f, "*reason* `{}`", reason)?;
                     }
@@ -98,3 +142,21 @@ impl std::fmt::Display for Result {
 pub trait Source {
     fn throw(&mut self, sides: u64) -> u64;
 }
+
+/// Interface for looking up the `@name` variables of a query, like character stats
+pub trait VariableResolver {
+    fn resolve(&self, name: &str) -> Option<i64>;
+}
+
+/// No variables at all
+impl VariableResolver for () {
+    fn resolve(&self, _name: &str) -> Option<i64> {
+        None
+    }
+}
+
+impl<S: BuildHasher> VariableResolver for HashMap<String, i64, S> {
+    fn resolve(&self, name: &str) -> Option<i64> {
+        self.get(name).copied()
+    }
+}
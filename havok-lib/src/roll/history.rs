@@ -7,12 +7,93 @@ use itertools::Itertools;
 pub enum History {
     OpenParen,
     CloseParen,
-    Fudge(Vec<u64>),
+    /// Fudge results in roll order
+    Fudge(Vec<dice::Result>),
     Operator(&'static str),
+    Function(&'static str),
+    Name(String),
+    /// Binding or `@name` variable, with the value it refers to
+    Reference(String, constant::Constant),
+    /// Dice results in roll order
     Roll(Vec<dice::Result>),
+    /// Tens and units dice of each percentile die
+    Percent(Vec<Percentile>),
+    /// Sets of dice of equal value
+    Sets(Vec<dice::Set>),
     Constant(constant::Constant),
 }
 
+/// Tens dice and units die of a percentile die, with the tens one kept
+#[derive(Debug, Clone)]
+pub struct Percentile {
+    pub tens: Vec<i64>,
+    pub units: i64,
+    pub kept: usize,
+}
+
+impl Percentile {
+    /// Value with the kept tens die, `00` and `0` being 100
+    pub fn value(&self) -> i64 {
+        percent_value(self.tens[self.kept], self.units)
+    }
+}
+
+/// Value of a tens die and a units die, `00` and `0` being 100
+pub(crate) fn percent_value(tens: i64, units: i64) -> i64 {
+    match tens + units {
+        0 => 100,
+        value => value,
+    }
+}
+
+/// Stringify dice results in roll order with the status of each one
+fn format_pool(pool: &[dice::Result], face: impl Fn(i64) -> String) -> String {
+    let mut s = String::from("[");
+    for (i, result) in pool.iter().enumerate() {
+        if i > 0 {
+            s.push_str(if result.is_added() { "+" } else { ", " });
+        }
+        let status = result.status;
+        let mut die = if status.penetrated {
+            format!("{}-1", face(result.value + 1))
+        } else {
+            face(result.value)
+        };
+        // an extra roll added into the dice already shows the explosion
+        if status.exploded && !pool.get(i + 1).is_some_and(dice::Result::is_added) {
+            die.push('!');
+        }
+        if status.wild && !result.is_added() {
+            die.push('ʷ');
+        }
+        if status.success {
+            die.push('✓');
+        } else if status.failure {
+            die.push('✗');
+        }
+        // a dice left out is struck through along with the rolls added into it
+        if !result.is_counted() {
+            if !result.is_added() || i == 0 || pool[i - 1].is_counted() {
+                s.push_str("~~");
+            }
+            s.push_str(&die);
+            if !pool
+                .get(i + 1)
+                .is_some_and(|r| r.is_added() && !r.is_counted())
+            {
+                s.push_str("~~");
+                if status.rerolled {
+                    s.push('↻');
+                }
+            }
+        } else {
+            s.push_str(&die);
+        }
+    }
+    s.push(']');
+    s
+}
+
 impl std::fmt::Display for History {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -20,21 +101,39 @@ impl std::fmt::Display for History {
             History::CloseParen => write!(f, ")")?,
             History::Fudge(v) => write!(
                 f,
-                "[{}]",
-                v.iter()
-                    .map(|r| match r {
-                        r if *r <= 2 => "-",
-                        r if *r <= 4 => "▢",
-                        _ => "+",
-                    })
-                    .format(", ")
+                "{}",
+                format_pool(v, |r| match r {
+                    r if r < 0 => "-".to_owned(),
+                    0 => "▢".to_owned(),
+                    _ => "+".to_owned(),
+                })
             )?,
             History::Operator(o) => write!(f, "{o}")?,
-            History::Roll(v) => write!(
+            History::Function(name) => write!(f, "{name}")?,
+            History::Name(name) => write!(f, "{name}")?,
+            History::Reference(name, value) => write!(f, "{name}({value})")?,
+            History::Roll(v) => write!(f, "{}", format_pool(v, |r| r.to_string()))?,
+            History::Percent(v) => write!(
                 f,
                 "[{}]",
-                v.iter().map(|r| r.value.to_string()).format(", ")
+                v.iter()
+                    .map(|p| format!(
+                        "{}+{}",
+                        p.tens
+                            .iter()
+                            .enumerate()
+                            .map(|(i, t)| if i == p.kept {
+                                format!("{t:02}")
+                            } else {
+                                format!("~~{t:02}~~")
+                            })
+                            .format("/"),
+                        p.units
+                    ))
+                    .format(", ")
             )?,
+            History::Sets(v) if v.is_empty() => write!(f, "no set")?,
+            History::Sets(v) => write!(f, "{}", v.iter().format(", "))?,
             History::Constant(v) => write!(f, "{v}")?,
         }
         Ok(())
@@ -1,10 +1,24 @@
+use crate::ast;
+use crate::ast::Span;
 use crate::constant;
 use crate::dice;
+use crate::error::Error;
 use crate::error::Result;
 use crate::roll::history::History;
+use crate::roll::history::Percentile;
 use crate::roll::kind;
 use std::ops::Deref;
 
+/// Total and fractional value of a result, from `int` when it's an integer that fits
+fn exact(int: Option<i64>, float: impl FnOnce() -> f64) -> Option<(i64, Option<f64>)> {
+    if let Some(total) = int {
+        return Some((total, None));
+    }
+    let value = float();
+    let total = constant::truncate(value)?;
+    Some((total, (value.fract() != 0.0).then_some(value)))
+}
+
 fn merge_history(lhs: &mut Single, rhs: &mut Single, oper: &'static str) {
     if !rhs.history.is_empty() {
         lhs.history.push(History::Operator(oper));
@@ -19,8 +33,13 @@ pub struct Single {
     total: i64,
     /// dummy flag to avoid re-computing a total
     dirty: bool,
+    /// Exact value, when it's a float constant or not an integer
     constant: Option<f64>,
     history: Vec<History>,
+    /// Worst glitch of the Shadowrun pools rolled
+    glitch: Option<dice::Glitch>,
+    /// Sets of dice of equal value matched by the rolls
+    sets: Vec<dice::Set>,
 }
 
 impl Single {
@@ -30,6 +49,8 @@ impl Single {
             dirty: true,
             constant: None,
             history: Vec::new(),
+            glitch: None,
+            sets: Vec::new(),
         }
     }
 
@@ -40,34 +61,161 @@ impl Single {
             dirty: false,
             constant: None,
             history: vec![History::Constant(constant::Constant::Integer(total))],
+            glitch: None,
+            sets: Vec::new(),
         }
     }
 
-    /// New with already a total that contains a float constant
-    pub fn with_float(float: f64) -> Self {
-        Self {
-            total: float as i64,
+    /// New with already a total that contains a float constant, `None` if it doesn't fit
+    pub fn with_float(float: f64) -> Option<Self> {
+        Some(Self {
+            total: constant::truncate(float)?,
             dirty: false,
             constant: Some(float),
             history: vec![History::Constant(constant::Constant::Float(float))],
-        }
+            glitch: None,
+            sets: Vec::new(),
+        })
     }
 
     pub fn get_history(&self) -> &Vec<History> {
         &self.history
     }
 
-    /// Add a step in the history
-    pub fn add_history(&mut self, mut history: Vec<dice::Result>, is_fudge: bool) {
+    /// Add a step in the history, with the dice in roll order
+    pub fn add_history(&mut self, history: Vec<dice::Result>, is_fudge: bool) {
         self.dirty = true;
-        history.sort_unstable_by(|a, b| b.cmp(a));
         self.history.push(if is_fudge {
-            History::Fudge(history.iter().map(|r| r.value).collect())
+            History::Fudge(history)
         } else {
             History::Roll(history)
         });
     }
 
+    /// Sort the dice of each roll for display, extra rolls stay with the dice they add into
+    pub fn sort_history(&mut self, descending: bool) {
+        for history in self.history.iter_mut() {
+            if let History::Roll(pool) | History::Fudge(pool) = history {
+                let mut units: Vec<Vec<dice::Result>> = Vec::new();
+                for result in pool.drain(..) {
+                    match units.last_mut() {
+                        Some(unit) if result.is_added() => unit.push(result),
+                        _ => units.push(vec![result]),
+                    }
+                }
+                let total = |unit: &Vec<dice::Result>| unit.iter().map(|r| r.value).sum::<i64>();
+                units.sort_by(|a, b| {
+                    let order = total(a).cmp(&total(b));
+                    if descending {
+                        order.reverse()
+                    } else {
+                        order
+                    }
+                });
+                pool.extend(units.into_iter().flatten());
+            }
+        }
+    }
+
+    /// Result of a group, the totals left out by a keep or drop are struck through
+    /// and the ones counted by a target are marked
+    pub fn with_group(items: Vec<Self>, status: &[dice::Status], total: i64) -> Self {
+        let glitch = items.iter().filter_map(|item| item.glitch).max();
+        let mut sets = Vec::new();
+        let mut history = vec![History::Operator("{")];
+        for (i, (mut item, status)) in items.into_iter().zip(status).enumerate() {
+            if i > 0 {
+                history.push(History::Operator(", "));
+            }
+            if status.dropped {
+                history.push(History::Operator("~~"));
+            }
+            history.append(&mut item.history);
+            sets.append(&mut item.sets);
+            if status.success {
+                history.push(History::Operator("✓"));
+            } else if status.failure {
+                history.push(History::Operator("✗"));
+            }
+            if status.dropped {
+                history.push(History::Operator("~~"));
+            }
+        }
+        history.push(History::Operator("}"));
+        Single {
+            total,
+            dirty: false,
+            constant: None,
+            history,
+            glitch,
+            sets,
+        }
+    }
+
+    /// Result of dice whose amount or sides were rolled first, shown before the dice
+    pub fn with_counts(
+        amount: Option<Self>,
+        sides: Option<Self>,
+        dice: &ast::Dice,
+        roll: Self,
+    ) -> Self {
+        let count = |history: &mut Vec<History>, single: Option<Self>, written: String| match single
+        {
+            Some(mut single) => {
+                history.push(History::OpenParen);
+                history.append(&mut single.history);
+                history.push(History::CloseParen);
+            }
+            None => history.push(History::Name(written)),
+        };
+        let mut history = Vec::new();
+        count(&mut history, amount, dice.amount.to_string());
+        history.push(History::Operator("d"));
+        count(&mut history, sides, dice.sides.to_string());
+        history.extend(dice.modifiers.iter().map(|m| History::Name(m.to_string())));
+        history.push(History::Operator(" → "));
+        history.extend(roll.history);
+        Single { history, ..roll }
+    }
+
+    /// Add a step with the matched sets, their count becomes the total when `count` is set
+    pub fn add_sets(&mut self, sets: Vec<dice::Set>, count: bool) {
+        if count {
+            self.total = sets.len() as i64;
+        }
+        self.history.push(History::Operator(" → "));
+        self.history.push(History::Sets(sets.clone()));
+        self.sets.extend(sets);
+    }
+
+    /// Add a step naming the outcome of the roll
+    pub fn add_outcome(&mut self, outcome: &'static str) {
+        self.history.push(History::Operator(" → "));
+        self.history.push(History::Function(outcome));
+    }
+
+    /// Add a step naming the glitch of a Shadowrun pool
+    pub fn add_glitch(&mut self, glitch: dice::Glitch) {
+        self.glitch = self.glitch.max(Some(glitch));
+        self.add_outcome(glitch.name());
+    }
+
+    /// Worst glitch of the Shadowrun pools rolled, if any
+    pub fn get_glitch(&self) -> Option<dice::Glitch> {
+        self.glitch
+    }
+
+    /// Sets of dice of equal value matched by the rolls
+    pub fn get_sets(&self) -> &[dice::Set] {
+        &self.sets
+    }
+
+    /// Add a step with the tens and units dice of each percentile die
+    pub fn add_percent(&mut self, dice: Vec<Percentile>) {
+        self.dirty = true;
+        self.history.push(History::Percent(dice));
+    }
+
     pub fn add_parens(&mut self) {
         self.history.insert(0, History::OpenParen);
         self.history.push(History::CloseParen);
@@ -77,78 +225,24 @@ impl Single {
     pub fn eval_total(&mut self, modifier: dice::Modifier) -> Result<i64> {
         if self.dirty {
             self.dirty = false;
-            let mut values = self.history.iter().fold(Vec::new(), |mut acc, history| {
-                match history {
-                    History::Roll(r) => {
-                        let mut c = r.iter().map(|u| u.value as i64).collect();
-                        acc.append(&mut c);
-                    }
-                    History::Fudge(r) => {
-                        let mut c = r.iter().map(|u| *u as i64).collect();
-                        acc.append(&mut c);
+            let mut total = 0i64;
+            for history in self.history.iter_mut() {
+                let value = match history {
+                    History::Roll(pool) | History::Fudge(pool) => dice::score(pool, &modifier),
+                    History::Percent(p) => {
+                        p.iter().try_fold(0i64, |acc, p| acc.checked_add(p.value()))
                     }
-                    History::Constant(v) => acc.push(v.get_value()),
-                    _ => (),
+                    History::Constant(v) => v.get_value(),
+                    _ => Some(0),
                 };
-                acc
-            });
-            values.sort_unstable();
-            let values = values;
-            match modifier {
-                dice::Modifier::KeepHigh(n)
-                | dice::Modifier::KeepLow(n)
-                | dice::Modifier::DropHigh(n)
-                | dice::Modifier::DropLow(n) => {
-                    if n > values.len() {
-                        return Err("Not enough dice to keep or drop".into());
-                    }
-                }
-                dice::Modifier::None(_)
-                | dice::Modifier::TargetDoubleFailure(_, _, _)
-                | dice::Modifier::TargetEnum(_)
-                | dice::Modifier::Fudge => (),
+                total =
+                    value
+                        .and_then(|value| total.checked_add(value))
+                        .ok_or(Error::Overflow {
+                            span: Span::default(),
+                        })?;
             }
-            let values = match modifier {
-                dice::Modifier::KeepHigh(n) => &values[values.len() - n..],
-                dice::Modifier::KeepLow(n) => &values[..n],
-                dice::Modifier::DropHigh(n) => &values[..values.len() - n],
-                dice::Modifier::DropLow(n) => &values[n..],
-                dice::Modifier::None(_)
-                | dice::Modifier::TargetDoubleFailure(_, _, _)
-                | dice::Modifier::TargetEnum(_)
-                | dice::Modifier::Fudge => values.as_slice(),
-            };
-            self.total = match modifier {
-                dice::Modifier::TargetDoubleFailure(t, f, d) => values.iter().fold(0, |acc, &x| {
-                    let x = x as u64;
-                    if d > 0 && x >= d {
-                        acc + 2
-                    } else if t > 0 && x >= t {
-                        acc + 1
-                    } else if f > 0 && x <= f {
-                        acc - 1
-                    } else {
-                        acc
-                    }
-                }),
-                dice::Modifier::TargetEnum(v) => values.iter().fold(0, |acc, &x| {
-                    if v.contains(&(x as u64)) {
-                        acc + 1
-                    } else {
-                        acc
-                    }
-                }),
-                dice::Modifier::Fudge => values.iter().fold(0, |acc, &x| {
-                    if x <= 2 {
-                        acc - 1
-                    } else if x <= 4 {
-                        acc
-                    } else {
-                        acc + 1
-                    }
-                }),
-                _ => values.iter().sum::<i64>(),
-            };
+            self.total = total;
         }
         Ok(self.total)
     }
@@ -166,108 +260,166 @@ impl Single {
         }
     }
 
-    /// Stringify history
-    pub fn to_string_history(&self) -> String {
-        self.history.iter().fold(String::new(), |mut s, v| {
-            s.push_str(v.to_string().as_str());
-            s
-        })
+    /// Exact value, with its fractional part if any
+    pub fn get_value(&self) -> f64 {
+        self.constant.unwrap_or(self.total as f64)
     }
 
-    /// Stringify self with(out) markdown formatting
-    pub fn to_string(&self) -> String {
-        if self.history.is_empty() {
-            format!("`{}`", self.total)
-        } else {
-            format!("`{}` = **{}**", self.to_string_history(), self.get_total())
-        }
-    }
-}
-
-impl std::ops::Add for Single {
-    type Output = Self;
-
-    fn add(mut self, mut rhs: Self) -> Self::Output {
-        merge_history(&mut self, &mut rhs, " + ");
-        let total = match (self.constant, rhs.constant) {
-            (None, None) => self.total + rhs.total,
-            (None, Some(c)) => (self.total as f64 + c).trunc() as i64,
-            (Some(c), None) => (c + rhs.total as f64).trunc() as i64,
-            (Some(l), Some(r)) => (l + r).trunc() as i64,
+    /// Apply an operator, `None` if the total overflows
+    fn checked_op(mut self, mut rhs: Self, op: ast::Operator, oper: &'static str) -> Option<Self> {
+        let int = match (self.constant, rhs.constant) {
+            (None, None) => op.int(self.total, rhs.total),
+            _ => None,
         };
-        Single {
+        let (total, constant) = exact(int, || op.float(self.get_value(), rhs.get_value()))?;
+        merge_history(&mut self, &mut rhs, oper);
+        self.sets.append(&mut rhs.sets);
+        Some(Single {
             total,
             dirty: false,
-            constant: None,
+            constant,
             history: self.history,
-        }
+            glitch: self.glitch.max(rhs.glitch),
+            sets: self.sets,
+        })
+    }
+
+    pub fn checked_add(self, rhs: Self) -> Option<Self> {
+        self.checked_op(rhs, ast::Operator::Add, " + ")
+    }
+
+    pub fn checked_sub(self, rhs: Self) -> Option<Self> {
+        self.checked_op(rhs, ast::Operator::Sub, " - ")
     }
-}
 
-impl std::ops::Sub for Single {
-    type Output = Self;
+    pub fn checked_mul(self, rhs: Self) -> Option<Self> {
+        self.checked_op(rhs, ast::Operator::Mul, " * ")
+    }
+
+    /// `None` on overflow or division by zero
+    pub fn checked_div(self, rhs: Self) -> Option<Self> {
+        self.checked_op(rhs, ast::Operator::Div, " / ")
+    }
+
+    /// `None` on overflow or division by zero
+    pub fn checked_rem(self, rhs: Self) -> Option<Self> {
+        self.checked_op(rhs, ast::Operator::Rem, " % ")
+    }
+
+    pub fn checked_pow(self, rhs: Self) -> Option<Self> {
+        self.checked_op(rhs, ast::Operator::Pow, " ** ")
+    }
 
-    fn sub(mut self, mut rhs: Self) -> Self::Output {
-        merge_history(&mut self, &mut rhs, " - ");
-        let total = match (self.constant, rhs.constant) {
-            (None, None) => self.total - rhs.total,
-            (None, Some(c)) => (self.total as f64 - c).trunc() as i64,
-            (Some(c), None) => (c - rhs.total as f64).trunc() as i64,
-            (Some(l), Some(r)) => (l - r).trunc() as i64,
+    /// Compare to `rhs`, the total is 1 when it holds and 0 otherwise
+    pub fn checked_compare(self, rhs: Self, op: ast::Operator) -> Option<Self> {
+        let oper = match op {
+            ast::Operator::Lt => " < ",
+            ast::Operator::Le => " <= ",
+            ast::Operator::Gt => " > ",
+            ast::Operator::Ge => " >= ",
+            ast::Operator::Eq => " == ",
+            ast::Operator::Ne => " != ",
+            _ => return None,
         };
+        self.checked_op(rhs, op, oper)
+    }
+
+    /// Result of the branch taken after the condition `cond`
+    pub fn with_branch(mut cond: Self, taken: bool, mut branch: Self) -> Self {
+        let mut history = vec![History::Operator("if ")];
+        history.append(&mut cond.history);
+        history.push(History::Operator(if taken { " then " } else { " else " }));
+        history.append(&mut branch.history);
+        cond.sets.append(&mut branch.sets);
         Single {
-            total,
-            dirty: false,
-            constant: None,
-            history: self.history,
+            history,
+            glitch: cond.glitch.max(branch.glitch),
+            sets: cond.sets,
+            ..branch
         }
     }
-}
-
-impl std::ops::Mul for Single {
-    type Output = Self;
 
-    fn mul(mut self, mut rhs: Self) -> Self::Output {
-        merge_history(&mut self, &mut rhs, " * ");
-        let total = match (self.constant, rhs.constant) {
-            (None, None) => self.total * rhs.total,
-            (None, Some(c)) => (self.total as f64 * c).trunc() as i64,
-            (Some(c), None) => (c * rhs.total as f64).trunc() as i64,
-            (Some(l), Some(r)) => (l * r).trunc() as i64,
+    /// Call a function on the arguments, `None` if the total overflows
+    pub fn checked_call(function: ast::Function, args: Vec<Self>) -> Option<Self> {
+        let int = if args.iter().all(|arg| arg.constant.is_none()) {
+            function.int(&args.iter().map(|arg| arg.total).collect::<Vec<_>>())
+        } else {
+            None
         };
-        Single {
+        let values = args.iter().map(Self::get_value).collect::<Vec<_>>();
+        let glitch = args.iter().filter_map(|arg| arg.glitch).max();
+        let (total, constant) = exact(int, || function.float(&values))?;
+        let mut sets = Vec::new();
+        let mut history = vec![History::Function(function.name()), History::OpenParen];
+        for (i, mut arg) in args.into_iter().enumerate() {
+            if i > 0 {
+                history.push(History::Operator(", "));
+            }
+            history.append(&mut arg.history);
+            sets.append(&mut arg.sets);
+        }
+        history.push(History::CloseParen);
+        if function == ast::Function::Coc {
+            history.push(History::Operator(" → "));
+            history.push(History::Function(ast::coc_name(total)));
+        }
+        Some(Single {
             total,
             dirty: false,
-            constant: None,
-            history: self.history,
-        }
+            constant,
+            history,
+            glitch,
+            sets,
+        })
     }
-}
 
-impl std::ops::Div for Single {
-    type Output = Self;
+    pub fn checked_neg(mut self) -> Option<Self> {
+        self.total = self.total.checked_neg()?;
+        self.constant = self.constant.map(|c| -c);
+        self.history.insert(0, History::Operator("-"));
+        Some(self)
+    }
 
-    fn div(mut self, mut rhs: Self) -> Self::Output {
-        merge_history(&mut self, &mut rhs, " / ");
-        let total = match (self.constant, rhs.constant) {
-            (None, None) => self.total / rhs.total,
-            (None, Some(c)) => (self.total as f64 / c).trunc() as i64,
-            (Some(c), None) => (c / rhs.total as f64).trunc() as i64,
-            (Some(l), Some(r)) => (l / r).trunc() as i64,
+    /// Same result, shown as a reference to the binding or variable `name` with its value
+    pub fn named(&self, name: &str) -> Self {
+        let value = match self.constant {
+            Some(float) => constant::Constant::Float(float),
+            None => constant::Constant::Integer(self.total),
         };
         Single {
-            total,
-            dirty: false,
-            constant: None,
-            history: self.history,
+            history: vec![History::Reference(name.to_owned(), value)],
+            ..self.clone()
         }
     }
+
+    /// Stringify history
+    pub fn to_string_history(&self) -> String {
+        self.history.iter().fold(String::new(), |mut s, v| {
+            s.push_str(v.to_string().as_str());
+            s
+        })
+    }
+}
+
+impl Default for Single {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
+/// Stringify self with markdown formatting
 impl std::fmt::Display for Single {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.to_string())?;
-        Ok(())
+        if self.history.is_empty() {
+            write!(f, "`{}`", self.total)
+        } else {
+            write!(
+                f,
+                "`{}` = **{}**",
+                self.to_string_history(),
+                self.get_total()
+            )
+        }
     }
 }
 
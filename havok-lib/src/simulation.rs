@@ -0,0 +1,178 @@
+use std::collections::BTreeMap;
+
+/// Confidence level used when displaying a simulation
+const DISPLAY_LEVEL: f64 = 0.95;
+
+/// Empirical distribution of a roll expression total, built by rolling it many times
+#[derive(Debug, Clone, Default)]
+pub struct Simulation {
+    histogram: BTreeMap<i64, u64>,
+    samples: u64,
+}
+
+impl Simulation {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record one rolled total
+    pub(crate) fn add(&mut self, total: i64) {
+        *self.histogram.entry(total).or_insert(0) += 1;
+        self.samples += 1;
+    }
+
+    /// How many times each total was rolled
+    pub fn histogram(&self) -> &BTreeMap<i64, u64> {
+        &self.histogram
+    }
+
+    pub fn samples(&self) -> u64 {
+        self.samples
+    }
+
+    /// Observed frequency of exactly `value`
+    pub fn frequency(&self, value: i64) -> f64 {
+        self.histogram.get(&value).copied().unwrap_or(0) as f64 / self.samples as f64
+    }
+
+    /// Observed frequency of `value` or more
+    pub fn at_least(&self, value: i64) -> f64 {
+        self.histogram.range(value..).map(|(_, n)| n).sum::<u64>() as f64 / self.samples as f64
+    }
+
+    /// Observed frequency of `value` or less
+    pub fn at_most(&self, value: i64) -> f64 {
+        self.histogram.range(..=value).map(|(_, n)| n).sum::<u64>() as f64 / self.samples as f64
+    }
+
+    pub fn min(&self) -> Option<i64> {
+        self.histogram.keys().next().copied()
+    }
+
+    pub fn max(&self) -> Option<i64> {
+        self.histogram.keys().next_back().copied()
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.histogram
+            .iter()
+            .map(|(v, n)| *v as f64 * *n as f64)
+            .sum::<f64>()
+            / self.samples as f64
+    }
+
+    /// Sample standard deviation
+    pub fn std_dev(&self) -> f64 {
+        if self.samples < 2 {
+            return 0.0;
+        }
+        let mean = self.mean();
+        let sum = self
+            .histogram
+            .iter()
+            .map(|(v, n)| (*v as f64 - mean).powi(2) * *n as f64)
+            .sum::<f64>();
+        (sum / (self.samples - 1) as f64).sqrt()
+    }
+
+    /// Smallest rolled value whose cumulative frequency reaches `percent` (0 to 100)
+    pub fn percentile(&self, percent: f64) -> Option<i64> {
+        let target = (percent.clamp(0.0, 100.0) / 100.0 * self.samples as f64).ceil() as u64;
+        let mut cumulative = 0;
+        for (value, n) in self.histogram.iter() {
+            cumulative += n;
+            if cumulative >= target {
+                return Some(*value);
+            }
+        }
+        self.max()
+    }
+
+    /// Confidence interval of the mean at `level` (e.g. `0.95`), normal approximation
+    pub fn confidence_interval(&self, level: f64) -> (f64, f64) {
+        let mean = self.mean();
+        let margin = z_score(level) * self.std_dev() / (self.samples as f64).sqrt();
+        (mean - margin, mean + margin)
+    }
+
+    /// Confidence interval at `level` of the probability to get `value` or more, Wilson score
+    pub fn at_least_interval(&self, value: i64, level: f64) -> (f64, f64) {
+        let n = self.samples as f64;
+        let p = self.at_least(value);
+        let z = z_score(level);
+        let denominator = 1.0 + z * z / n;
+        let center = (p + z * z / (2.0 * n)) / denominator;
+        let margin = z * (p * (1.0 - p) / n + z * z / (4.0 * n * n)).sqrt() / denominator;
+        ((center - margin).max(0.0), (center + margin).min(1.0))
+    }
+}
+
+impl std::fmt::Display for Simulation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (low, high) = self.confidence_interval(DISPLAY_LEVEL);
+        write!(
+            f,
+            "*mean* **{:.2}** *({:.2} to {:.2})* *std dev* **{:.2}** *min* **{}** *max* **{}** *samples* **{}**",
+            self.mean(),
+            low,
+            high,
+            self.std_dev(),
+            self.min().unwrap_or(0),
+            self.max().unwrap_or(0),
+            self.samples
+        )
+    }
+}
+
+/// Two sided standard normal quantile for a confidence `level`
+fn z_score(level: f64) -> f64 {
+    inverse_normal(0.5 + level.clamp(0.0, 0.999_999) / 2.0)
+}
+
+/// Standard normal quantile, Acklam's rational approximation
+fn inverse_normal(p: f64) -> f64 {
+    const A: [f64; 6] = [
+        -3.969683028665376e+01,
+        2.209460984245205e+02,
+        -2.759285104469687e+02,
+        1.38357751867269e+02,
+        -3.066479806614716e+01,
+        2.506628277459239e+00,
+    ];
+    const B: [f64; 5] = [
+        -5.447609879822406e+01,
+        1.615858368580409e+02,
+        -1.556989798598866e+02,
+        6.680131188771972e+01,
+        -1.328068155288572e+01,
+    ];
+    const C: [f64; 6] = [
+        -7.784894002430293e-03,
+        -3.223964580411365e-01,
+        -2.400758277161838e+00,
+        -2.549732539343734e+00,
+        4.374664141464968e+00,
+        2.938163982698783e+00,
+    ];
+    const D: [f64; 4] = [
+        7.784695709041462e-03,
+        3.224671290700398e-01,
+        2.445134137142996e+00,
+        3.754408661907416e+00,
+    ];
+    const LOW: f64 = 0.02425;
+    let tail = |q: f64| {
+        (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5])
+            / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0)
+    };
+    if p < LOW {
+        tail((-2.0 * p.ln()).sqrt())
+    } else if p > 1.0 - LOW {
+        -tail((-2.0 * (1.0 - p).ln()).sqrt())
+    } else {
+        let q = p - 0.5;
+        let r = q * q;
+        (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q
+            / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1.0)
+    }
+}
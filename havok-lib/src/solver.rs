@@ -1,33 +1,111 @@
+use crate::ast;
+use crate::calculator::Calculator;
 use crate::dice;
+use crate::distribution::Distribution;
+use crate::error::Error;
 use crate::error::Result;
 use crate::evaluator::Evaluator;
+use crate::limits::EvalLimits;
 use crate::parser;
 use crate::roll;
-use pest::iterators::Pair;
+use crate::simulation::Simulation;
 use pest::Parser;
 use rand::CryptoRng;
 use rand::Rng;
+use std::borrow::Cow;
 
 /// Default random dice roller
-pub(crate) struct RandomSource<'a, T: Rng + CryptoRng> {
+pub struct RandomSource<'a, T: Rng + CryptoRng> {
     pub(crate) generator: &'a mut T,
 }
 
+impl<'a, T: Rng + CryptoRng> RandomSource<'a, T> {
+    pub fn new(generator: &'a mut T) -> Self {
+        Self { generator }
+    }
+}
+
 impl<T: Rng + CryptoRng> roll::Source for RandomSource<'_, T> {
     fn throw(&mut self, sides: u64) -> u64 {
         self.generator.gen_range(1..1 + sides)
     }
 }
 
+/// Likelihood of each total of a roll expression
+#[derive(Debug, Clone)]
+pub enum Probability {
+    Exact(Distribution),
+    /// Estimated by rolling, when the exact distribution can't be computed
+    Simulated(Simulation),
+}
+
+impl std::fmt::Display for Probability {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Probability::Exact(distribution) => write!(f, "*exact* {}", distribution),
+            Probability::Simulated(simulation) => write!(f, "*simulated* {}", simulation),
+        }
+    }
+}
+
 const REASON_SIGIL: char = ':';
 
-/// Represent a solver and holds the query string
+/// Represent a solver and holds the query string, solving never panics whatever the query
 #[derive(Clone, Debug)]
-pub struct Solver(String);
+pub struct Solver {
+    input: String,
+    /// Typed expression, only kept once compiled
+    ast: Option<ast::Command>,
+    limits: EvalLimits,
+}
 
 impl Solver {
     pub fn new(input: &str) -> Result<Self> {
-        Ok(Solver(input.to_owned()))
+        Ok(Solver {
+            input: input.to_owned(),
+            ast: None,
+            limits: EvalLimits::default(),
+        })
+    }
+
+    /// New from an already typed expression
+    pub fn from_ast(ast: ast::Command) -> Self {
+        Solver {
+            input: ast.to_string(),
+            ast: Some(ast),
+            limits: EvalLimits::default(),
+        }
+    }
+
+    /// Replace the default limits on the work done to solve the query
+    pub fn with_limits(mut self, limits: EvalLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn limits(&self) -> &EvalLimits {
+        &self.limits
+    }
+
+    /// Parse the query once, so that bad input fails early and later solves skip parsing
+    pub fn compile(mut self) -> Result<Self> {
+        if self.ast.is_none() {
+            self.ast = Some(parser::Parser::parse_command(&self.input)?);
+        }
+        Ok(self)
+    }
+
+    /// Return the typed expression, if compiled
+    pub fn ast(&self) -> Option<&ast::Command> {
+        self.ast.as_ref()
+    }
+
+    /// Return the typed expression, parsing the query if not compiled
+    fn command(&self) -> Result<Cow<'_, ast::Command>> {
+        match &self.ast {
+            Some(ast) => Ok(Cow::Borrowed(ast)),
+            None => Ok(Cow::Owned(parser::Parser::parse_command(&self.input)?)),
+        }
     }
 
     /// Solve the roll expression using the default Rng source
@@ -42,66 +120,181 @@ impl Solver {
 
     /// Solve the roll expression using the provided source
     pub fn solve_with_source<S: roll::Source>(&self, source: &mut S) -> Result<roll::Result> {
-        let mut pairs = parser::Parser::parse(parser::Rule::command, &self.0)?;
-        let expr = pairs.next().unwrap();
-        let mut result = match expr.as_rule() {
-            parser::Rule::expr => {
-                roll::Result::new_single(Evaluator::eval(expr.into_inner(), source, false)?)
-            }
-            parser::Rule::repeated_expr => Solver::solve_multi(expr, source)?,
-            _ => unreachable!(),
-        };
-        if let Some(reason) = pairs.next() {
-            if reason.as_rule() == parser::Rule::reason {
-                result.add_reason(reason.as_str()[1..].trim().to_owned());
-            }
-        }
-        Ok(result)
+        self.solve_with_resolver(source, &())
     }
 
-    /// Solve a multi roll expression using the provided source
-    fn solve_multi<S: roll::Source>(
-        pairs: Pair<parser::Rule>,
+    /// Solve the roll expression using the provided source, `@name` variables come from `resolver`
+    pub fn solve_with_resolver<S: roll::Source, R: roll::VariableResolver>(
+        &self,
         source: &mut S,
+        resolver: &R,
     ) -> Result<roll::Result> {
-        let mut pairs = pairs.into_inner();
-        let expr = pairs.next().unwrap();
-        let repeat = pairs.next().unwrap();
-        let (iters, sum, sort) = match repeat.as_rule() {
-            parser::Rule::nb_dice => (repeat.as_str().parse::<u64>().unwrap(), false, false),
-            parser::Rule::add => (
-                pairs.next().unwrap().as_str().parse::<u64>().unwrap(),
-                true,
-                false,
-            ),
-            parser::Rule::sort => (
-                pairs.next().unwrap().as_str().parse::<u64>().unwrap(),
-                false,
-                true,
+        let command = self.command()?;
+        self.eval_command(&command, source, resolver, &self.limits)
+            .map(|(result, _)| result)
+    }
+
+    /// Evaluate the typed expression with a new evaluator, also returns the dices thrown
+    fn eval_command<S: roll::Source, R: roll::VariableResolver>(
+        &self,
+        command: &ast::Command,
+        source: &mut S,
+        resolver: &R,
+        limits: &EvalLimits,
+    ) -> Result<(roll::Result, u64)> {
+        let mut evaluator = Evaluator::new(source, resolver, limits);
+        for binding in &command.bindings {
+            evaluator.bind(binding)?;
+        }
+        let mut result = match &command.root {
+            ast::Root::Expr(expr) => roll::Result::new_single(evaluator.eval(expr)?),
+            ast::Root::Repeat(repeat) => self.solve_multi(repeat, &mut evaluator)?,
+            ast::Root::List(exprs) => roll::Result::new_multi(
+                exprs
+                    .iter()
+                    .map(|expr| evaluator.eval(expr))
+                    .collect::<Result<_>>()?,
+                None,
             ),
-            _ => unreachable!(),
         };
+        let thrown = evaluator.thrown();
+        result.add_bindings(evaluator.into_bound());
+        if let Some(reason) = &command.reason {
+            result.add_reason(reason.to_owned());
+        }
+        Ok((result, thrown))
+    }
+
+    /// Solve a multi roll expression using the provided evaluator
+    fn solve_multi<S: roll::Source>(
+        &self,
+        repeat: &ast::Repeat,
+        evaluator: &mut Evaluator<S>,
+    ) -> Result<roll::Result> {
+        if repeat.times > self.limits.max_repeat {
+            return Err(Error::TooManyRepeats {
+                span: repeat.span,
+                max: self.limits.max_repeat,
+            });
+        }
         let results: Result<Vec<roll::kind::Single>> =
-            (0..iters).try_fold(Vec::new(), |mut res, _| {
-                let c = Evaluator::eval(expr.clone().into_inner(), source, false)?;
+            (0..repeat.times).try_fold(Vec::new(), |mut res, _| {
+                let c = evaluator.eval(&repeat.expr)?;
                 res.push(c);
                 Ok(res)
             });
         let mut results = results?;
-        if sort {
-            results.sort_unstable_by(|a, b| a.get_total().partial_cmp(&b.get_total()).unwrap());
+        if repeat.mode == ast::RepeatMode::Sort {
+            results.sort_unstable_by_key(|result| result.get_total());
         }
-        let total = if sum {
-            Some(results.iter().fold(0, |acc, curr| acc + curr.get_total()))
+        let total = if repeat.mode == ast::RepeatMode::Sum {
+            let total = results
+                .iter()
+                .try_fold(0i64, |acc, curr| acc.checked_add(curr.get_total()))
+                .ok_or(Error::Overflow { span: repeat.span })?;
+            Some(total)
         } else {
             None
         };
         Ok(roll::Result::new_multi(results, total))
     }
 
+    /// Compute the exact probability distribution of the roll expression total
+    pub fn distribution(&self) -> Result<Distribution> {
+        self.distribution_with_resolver(&())
+    }
+
+    /// Compute the exact probability distribution, `@name` variables come from `resolver`
+    pub fn distribution_with_resolver<R: roll::VariableResolver>(
+        &self,
+        resolver: &R,
+    ) -> Result<Distribution> {
+        let command = self.command()?;
+        Calculator::calc_command(&command, resolver, &self.limits)
+    }
+
+    /// Roll the expression `samples` times using the provided source
+    pub fn simulate<S: roll::Source>(&self, samples: u64, source: &mut S) -> Result<Simulation> {
+        self.simulate_with_resolver(samples, source, &())
+    }
+
+    /// Roll the expression `samples` times, `@name` variables come from `resolver`
+    pub fn simulate_with_resolver<S: roll::Source, R: roll::VariableResolver>(
+        &self,
+        samples: u64,
+        source: &mut S,
+        resolver: &R,
+    ) -> Result<Simulation> {
+        if samples == 0 {
+            return Err(Error::NoSamples);
+        }
+        let command = self.command()?;
+        let root = &command.root;
+        match root {
+            ast::Root::Repeat(repeat) if repeat.mode != ast::RepeatMode::Sum => {
+                return Err(Error::NoTotal { span: repeat.span });
+            }
+            ast::Root::List(_) => return Err(Error::NoTotal { span: root.span() }),
+            _ => (),
+        }
+        let budget = self.limits.max_simulated_thrown;
+        let mut thrown = 0u64;
+        let mut simulation = Simulation::new();
+        for _ in 0..samples {
+            // a sample only throws the dices left in the budget of the whole simulation
+            let limits = EvalLimits {
+                max_dice_thrown: self.limits.max_dice_thrown.min(budget - thrown),
+                ..self.limits
+            };
+            let (result, count) = self
+                .eval_command(&command, source, resolver, &limits)
+                .map_err(|error| match error {
+                    Error::TooManyThrows { span, .. }
+                        if limits.max_dice_thrown < self.limits.max_dice_thrown =>
+                    {
+                        Error::TooManyThrows { span, max: budget }
+                    }
+                    error => error,
+                })?;
+            thrown += count;
+            let total = match result.get_result() {
+                roll::Kind::Single(single) => single.get_total(),
+                roll::Kind::Multi(multi) => multi.get_total().unwrap_or_default(),
+            };
+            simulation.add(total);
+        }
+        Ok(simulation)
+    }
+
+    /// Exact distribution of the total, or a simulation of `samples` rolls when it's too costly
+    /// or not supported
+    pub fn probability<S: roll::Source>(
+        &self,
+        samples: u64,
+        source: &mut S,
+    ) -> Result<Probability> {
+        self.probability_with_resolver(samples, source, &())
+    }
+
+    /// Exact distribution or simulation of the total, `@name` variables come from `resolver`
+    pub fn probability_with_resolver<S: roll::Source, R: roll::VariableResolver>(
+        &self,
+        samples: u64,
+        source: &mut S,
+        resolver: &R,
+    ) -> Result<Probability> {
+        match self.distribution_with_resolver(resolver) {
+            Ok(distribution) => Ok(Probability::Exact(distribution)),
+            Err(Error::TooComplex { .. } | Error::NotExact { .. }) => self
+                .simulate_with_resolver(samples, source, resolver)
+                .map(Probability::Simulated),
+            Err(error) => Err(error),
+        }
+    }
+
     /// Return an iterator on the dices in the roll expression
-    pub fn dices(&self) -> Result<dice::Iter> {
-        let inner = parser::Parser::parse(parser::Rule::command, &self.0)?
+    pub fn dices(&self) -> Result<dice::Iter<'_>> {
+        let inner = parser::Parser::parse(parser::Rule::command, &self.input)?
             .next()
             .unwrap()
             .into_inner();
@@ -110,13 +303,16 @@ impl Solver {
 
     /// Return the query string
     pub fn as_str(&self) -> &str {
-        &self.0
+        &self.input
     }
 
     /// Removes the reason from the query
     pub fn trim_reason(&mut self) {
-        if let Some(index) = self.0.find(REASON_SIGIL) {
-            self.0 = self.0[..index].to_owned()
+        if let Some(index) = self.input.find(REASON_SIGIL) {
+            self.input = self.input[..index].to_owned()
+        }
+        if let Some(ast) = &mut self.ast {
+            ast.reason = None;
         }
     }
 }
@@ -2,6 +2,7 @@ mod helper;
 use helper::ReplHelper;
 
 use colored::Colorize;
+use havok_lib::solver::RandomSource;
 use havok_lib::solver::Solver;
 use rustyline::completion::FilenameCompleter;
 use rustyline::error::ReadlineError;
@@ -17,6 +18,24 @@ use termimad::crossterm::style::Color;
 use termimad::MadSkin;
 
 const HISTORY_FILE: &str = "history";
+const PROBABILITY_SIGIL: char = '?';
+const SIMULATION_SAMPLES: u64 = 10_000;
+
+fn probability(input: &str) -> havok_lib::error::Result<String> {
+    Solver::new(input)?
+        .probability(
+            SIMULATION_SAMPLES,
+            &mut RandomSource::new(&mut rand::thread_rng()),
+        )
+        .map(|probability| probability.to_string())
+}
+
+fn format_error(input: &str, error: &havok_lib::error::Error) -> String {
+    match error.span() {
+        Some(span) => format!("{} {}\n{}", error.code(), error, span.underline(input)),
+        None => format!("{} {}", error.code(), error),
+    }
+}
 
 fn main() -> Result<()> {
     let config = Config::builder()
@@ -48,10 +67,17 @@ fn main() -> Result<()> {
         match readline {
             Ok(line) => {
                 rline.add_history_entry(line.as_str())?;
-                if !line.is_empty() {
-                    match Solver::new(line.as_str().trim()).unwrap().solve() {
+                if let Some(input) = line.trim().strip_prefix(PROBABILITY_SIGIL) {
+                    let input = input.trim();
+                    match probability(input) {
+                        Ok(result) => println!("{}", skin.inline(&result.magenta())),
+                        Err(error) => eprintln!("{}", format_error(input, &error).bold().red()),
+                    }
+                } else if !line.is_empty() {
+                    let input = line.as_str().trim();
+                    match Solver::new(input).unwrap().solve() {
                         Ok(result) => println!("{}", skin.inline(&format!("{}", result).magenta())),
-                        Err(error) => eprintln!("{}", format!("{}", error).bold().red()),
+                        Err(error) => eprintln!("{}", format_error(input, &error).bold().red()),
                     }
                 }
             }